@@ -1,104 +1,184 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::{
     error::{Error, ErrorType},
+    nodes::NodeId,
     token::{TType, Token},
     types::Type,
 };
 
+type Scope = Rc<RefCell<Vec<Type>>>;
+
+/// Where the resolver found a variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    /// how many scopes out from the innermost one, and the index in that scope
+    Local(usize, usize),
+    /// the index in the globals table
+    Global(usize),
+}
+
+/// Where each variable reference was resolved to, by node id. The ids of one
+/// file or REPL input are close together, so they are stored from the first.
+#[derive(Debug, Clone, Default)]
+pub struct Locals {
+    first: NodeId,
+    slots: Vec<Option<Slot>>,
+}
+
+impl Locals {
+    pub fn get(&self, id: NodeId) -> Option<Slot> {
+        *self.slots.get(id.checked_sub(self.first)?)?
+    }
+
+    pub fn insert(&mut self, id: NodeId, slot: Slot) {
+        if self.slots.is_empty() {
+            self.first = id;
+        } else if id < self.first {
+            let mut slots = vec![None; self.first - id];
+            slots.append(&mut self.slots);
+            self.slots = slots;
+            self.first = id;
+        }
+
+        let i = id - self.first;
+        if self.slots.len() <= i {
+            self.slots.resize(i + 1, None);
+        }
+
+        self.slots[i] = Some(slot);
+    }
+}
+
+/// Top-level variables. Slots are handed out by the resolver before the
+/// variable is defined, so a slot can still be empty.
+#[derive(Debug, Default)]
+struct Globals {
+    slots: HashMap<String, usize>,
+    values: Vec<Option<Type>>,
+}
+
+// Scopes are shared, so cloning an environment (e.g. when a function captures
+// the environment it was defined in) still sees and mutates the same variables.
+// Local scopes hold their variables in the order they are defined, which is
+// the order the resolver numbered them in.
 #[derive(Debug, Clone)]
 pub struct Environment {
-    scopes: Vec<HashMap<String, Type>>,
+    globals: Rc<RefCell<Globals>>,
+    scopes: Vec<Scope>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            globals: Rc::new(RefCell::new(Globals::default())),
+            scopes: vec![],
         }
     }
 
     pub fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.scopes.push(Rc::new(RefCell::new(Vec::new())));
     }
 
     pub fn pop_scope(&mut self) {
         self.scopes.pop();
     }
 
-    pub fn get(&self, tok: &Token) -> Result<Type, Error> {
-        if let TType::Identifier(name) = &tok.ttype {
-            let mut scopes = self.scopes.clone();
-            scopes.reverse();
+    /// The defined top-level variables.
+    pub fn globals(&self) -> HashMap<String, Type> {
+        let globals = self.globals.borrow();
 
-            for scope in scopes {
-                if scope.contains_key(name) {
-                    return Ok(scope[name].clone());
-                }
-            }
+        globals
+            .slots
+            .iter()
+            .filter_map(|(name, slot)| Some((name.clone(), globals.values[*slot].clone()?)))
+            .collect()
+    }
 
-            Err(Error::new(
-                tok.lineinfo,
-                format!("Undefined variable {}.", name),
-                ErrorType::TypeError,
-            ))
-        } else {
-            panic!();
-        }
+    /// The current value of the top-level variable `name`, if it is defined.
+    pub fn global(&self, name: &str) -> Option<Type> {
+        let globals = self.globals.borrow();
+        globals.values[*globals.slots.get(name)?].clone()
     }
 
-    pub fn get_at(&mut self, distance: usize, name: &Token) -> Type {
-        if let TType::Identifier(n) = &name.ttype {
-            self.ancestor(distance).get(n).unwrap().clone()
-        } else {
-            panic!()
+    /// The names of the defined top-level variables.
+    pub fn global_names(&self) -> Vec<String> {
+        let globals = self.globals.borrow();
+
+        globals
+            .slots
+            .iter()
+            .filter(|(_, slot)| globals.values[**slot].is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The slot of the top-level variable `name`, made empty if it has none yet.
+    pub fn global_slot(&self, name: &str) -> usize {
+        let mut globals = self.globals.borrow_mut();
+
+        if let Some(slot) = globals.slots.get(name) {
+            return *slot;
         }
+
+        let slot = globals.values.len();
+        globals.values.push(None);
+        globals.slots.insert(name.into(), slot);
+
+        slot
     }
 
-    fn ancestor(&mut self, distance: usize) -> &mut HashMap<String, Type> {
-        let distance = self.scopes.len() - 1 - distance;
+    pub fn get(&self, slot: Slot, tok: &Token) -> Result<Type, Error> {
+        let val = match slot {
+            Slot::Local(depth, i) => self.scope(depth).borrow().get(i).cloned(),
+            Slot::Global(i) => self.globals.borrow().values[i].clone(),
+        };
 
-        &mut self.scopes[distance]
+        val.ok_or_else(|| Self::undefined(tok))
     }
 
     pub fn define(&mut self, name: &String, val: &Type) {
-        self.scopes
-            .last_mut()
-            .unwrap()
-            .insert(name.clone(), val.clone());
+        match self.scopes.last() {
+            Some(scope) => scope.borrow_mut().push(val.clone()),
+            None => {
+                let slot = self.global_slot(name);
+                self.globals.borrow_mut().values[slot] = Some(val.clone());
+            }
+        }
     }
 
-    pub fn assign(&mut self, name: &Token, val: &Type) -> Result<(), Error> {
-        if let TType::Identifier(n) = name.ttype.clone() {
-            let mut scopes = self.scopes.clone();
-            scopes.reverse();
-
-            for (i, scope) in scopes.iter().enumerate() {
-                if scope.contains_key(&n) {
-                    let mut scope = self.scopes[i].clone();
-                    scope.insert(n, val.clone());
-                    self.scopes[i] = scope;
+    pub fn assign(&mut self, slot: Slot, tok: &Token, val: &Type) -> Result<(), Error> {
+        match slot {
+            Slot::Local(depth, i) => {
+                if let Some(var) = self.scope(depth).borrow_mut().get_mut(i) {
+                    *var = val.clone();
+                    return Ok(());
+                }
+            }
+            Slot::Global(i) => {
+                if let Some(var) = &mut self.globals.borrow_mut().values[i] {
+                    *var = val.clone();
                     return Ok(());
                 }
             }
-
-            Err(Error::new(
-                name.lineinfo,
-                format!("Undefined variable {}.", n),
-                ErrorType::ReferenceError,
-            ))
-        } else {
-            panic!();
         }
+
+        Err(Self::undefined(tok))
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &Token, val: &Type) -> Result<(), Error> {
-        if let TType::Identifier(n) = &name.ttype {
-            self.ancestor(distance).insert(n.clone(), val.clone());
-        } else {
-            panic!();
-        }
+    fn scope(&self, depth: usize) -> &Scope {
+        &self.scopes[self.scopes.len() - 1 - depth]
+    }
 
-        Ok(())
+    /// The error for a variable used before its definition has run.
+    pub fn undefined(tok: &Token) -> Error {
+        match &tok.ttype {
+            TType::Identifier(name) => Error::new(
+                tok.lineinfo,
+                format!("Undefined variable {}.", name),
+                ErrorType::ReferenceError,
+            ),
+            _ => panic!(),
+        }
     }
 }
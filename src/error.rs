@@ -1,4 +1,8 @@
-use crate::types::Type;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use maplit::hashmap;
+
+use crate::types::{map::Map, Type};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct LineInfo {
@@ -18,10 +22,41 @@ pub enum ErrorType {
     TypeError,
     SyntaxError,
     ReferenceError,
+    ImportError,
+    MatchError,
+    /// Calls nested deeper than `Loader::max_depth`.
+    RecursionError,
+    /// A value raised with `throw`.
+    Thrown(Box<Type>),
+    /// Not an error: something the resolver thinks is a mistake, which is
+    /// only fatal with `--deny-warnings`.
+    Warning(WarningType),
+}
 
-    Break,
-    Continue,
-    Return(Type),
+#[derive(Clone, Debug, PartialEq)]
+pub enum WarningType {
+    UnusedVariable,
+    UnusedImport,
+    Shadowing,
+    Unreachable,
+}
+
+impl ErrorType {
+    /// The name shown for the error, and the "type" a `catch` block sees.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Thrown(value) => match value.as_ref() {
+                Type::Map(map) => map
+                    .borrow()
+                    .map
+                    .get("type")
+                    .map_or("Error".into(), |t| t.to_string()),
+                _ => "Error".into(),
+            },
+            Self::Warning(_) => "Warning".into(),
+            _ => format!("{:?}", self),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -30,6 +65,34 @@ pub enum ErrorNote {
     Expect(LineInfo, String),
 }
 
+/// A file other than the one being run, so errors raised inside imported modules
+/// can be displayed against the right code.
+#[derive(Debug)]
+pub struct Source {
+    pub path: String,
+    pub code: String,
+}
+
+/// A function call in progress: what was called, and where from.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub name: String,
+    pub call: LineInfo,
+    /// the file the call is in, if not the main program
+    pub source: Option<Rc<Source>>,
+}
+
+impl Frame {
+    fn same_call(&self, other: &Frame) -> bool {
+        let same_source = match (&self.source, &other.source) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+
+        self.name == other.name && self.call == other.call && same_source
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Error {
     info: LineInfo,
@@ -39,6 +102,49 @@ pub struct Error {
     #[cfg(not(test))]
     error: String,
     notes: Vec<ErrorNote>,
+    source: Option<Rc<Source>>,
+    /// whether `source` has been decided; `None` is the main program
+    sourced: bool,
+    /// the calls that led to the error, outermost first
+    trace: Vec<Frame>,
+}
+
+/// Suggest the candidate closest to a misspelled `name`, if any is close enough.
+pub fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a String>,
+) -> Option<ErrorNote> {
+    let max = (name.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(dist, _)| *dist <= max)
+        .min()
+        .map(|(_, candidate)| ErrorNote::Note(format!("Did you mean '{}'?", candidate)))
+}
+
+// levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(row[j]).min(cur)
+            };
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
 }
 
 impl Error {
@@ -48,6 +154,9 @@ impl Error {
             error,
             error_type,
             notes: Vec::new(),
+            source: None,
+            sourced: false,
+            trace: Vec::new(),
         }
     }
 
@@ -62,16 +171,103 @@ impl Error {
             error,
             error_type,
             notes,
+            source: None,
+            sourced: false,
+            trace: Vec::new(),
         }
     }
 
+    /// An error raised by `throw`. A thrown map can set the "message" (and the
+    /// "type", see `ErrorType::name`); any other value is the message itself.
+    pub fn thrown(info: LineInfo, value: Type) -> Self {
+        let message = match &value {
+            Type::Map(map) => map.borrow().map.get("message").map(|m| m.to_string()),
+            _ => None,
+        };
+
+        Self::new(
+            info,
+            message.unwrap_or_else(|| value.to_string()),
+            ErrorType::Thrown(Box::new(value)),
+        )
+    }
+
+    /// The map a `catch` block receives: the error's type, message, line and
+    /// column. A thrown map is passed through with those keys filled in, so
+    /// rethrowing it keeps its original position; any other thrown value is
+    /// kept under "value".
+    pub fn to_value(&self) -> Type {
+        let mut map = match &self.error_type {
+            ErrorType::Thrown(value) => match value.as_ref() {
+                Type::Map(map) => map.borrow().map.clone(),
+                value => hashmap! { "value".into() => value.clone() },
+            },
+            _ => HashMap::new(),
+        };
+
+        map.entry("type".into())
+            .or_insert_with(|| Type::String(self.error_type.name()));
+        map.entry("message".into())
+            .or_insert_with(|| Type::String(self.error.clone()));
+        map.entry("line".into())
+            .or_insert(Type::Int(self.info.line as i64));
+        map.entry("column".into())
+            .or_insert(Type::Int(self.info.col as i64));
+
+        Type::Map(Rc::new(RefCell::new(Map::new(map))))
+    }
+
+    /// Move the error to `info`. Native functions don't know where they were
+    /// called from, so the interpreter places their errors at the call.
+    pub fn at(mut self, info: LineInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Attach the call stack the error happened in, unless it already has one.
+    pub fn with_trace(mut self, frames: &[Frame]) -> Self {
+        if self.trace.is_empty() {
+            self.trace = frames.to_vec();
+        }
+
+        self
+    }
+
+    /// Attach the file the error happened in, unless it already has one.
+    pub fn in_source(mut self, source: &Option<Rc<Source>>) -> Self {
+        if !self.sourced {
+            self.source = source.clone();
+            self.sourced = true;
+        }
+
+        self
+    }
+
+    pub fn is_warning(&self) -> bool {
+        matches!(self.error_type, ErrorType::Warning(_))
+    }
+
+    pub fn line(&self) -> i32 {
+        self.info.line
+    }
+
     pub fn display(&self, code: &String) {
         let line = self.info.line as usize;
         let col = self.info.col as usize;
 
+        let (code, file) = match &self.source {
+            Some(source) => (&source.code, format!("\x1b[1m{}\x1b[0m:", source.path)),
+            None => (code, String::new()),
+        };
+
         let message = format!(
-            "[\x1b[1m{}\x1b[0m:\x1b[1m{}\x1b[0m] \x1b[1m{:?}\x1b[0m: \x1b[31m\x1b[1m{}\x1b[0m",
-            line, col, self.error_type, self.error
+            "[{}\x1b[1m{}\x1b[0m:\x1b[1m{}\x1b[0m] \x1b[1m{}\x1b[0m: \x1b[{}m\x1b[1m{}\x1b[0m",
+            file,
+            line,
+            col,
+            self.error_type.name(),
+            if self.is_warning() { 33 } else { 31 },
+            self.error
         );
         let gutter = format!("\x1b[1m{}\x1b[0m | ", line);
         let editor = format!(
@@ -117,12 +313,50 @@ impl Error {
         eprintln!(
             "{msg}
 {edt}
-\x1b[94m{arrow:->length$} here\x1b[0m{notes}",
+\x1b[94m{arrow:->length$} here\x1b[0m{notes}{trace}",
             msg = message,
             edt = editor,
             length = col + gutter.len() - 8, // - \x1b[1m\x1b[0m
             arrow = '^',
-            notes = notes
+            notes = notes,
+            trace = self.traceback(code),
         );
     }
+
+    /// The call stack, innermost last, with the line each call was made on.
+    fn traceback(&self, code: &str) -> String {
+        if self.trace.is_empty() {
+            return String::new();
+        }
+
+        let mut out = String::from("\n\n\x1b[1mTraceback\x1b[0m (innermost last):");
+
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            // runaway recursion makes the same call over and over
+            let mut repeats = 0;
+            while frames.next_if(|next| frame.same_call(next)).is_some() {
+                repeats += 1;
+            }
+
+            let (code, file) = match &frame.source {
+                Some(source) => (source.code.as_str(), format!("{}:", source.path)),
+                None => (code, String::new()),
+            };
+            let line = frame.call.line as usize;
+
+            out += &format!("\n  {}{}:{} in {}", file, line, frame.call.col, frame.name);
+
+            // a call with no known line (0) has no source to show
+            if let Some(text) = line.checked_sub(1).and_then(|i| code.split('\n').nth(i)) {
+                out += &format!("\n    \x1b[1m{}\x1b[0m | {}", line, text.trim());
+            }
+
+            if repeats > 0 {
+                out += &format!("\n  [the call above repeated {} more times]", repeats);
+            }
+        }
+
+        out
+    }
 }
@@ -0,0 +1,68 @@
+use std::{collections::HashMap, fmt::Debug, rc::Rc};
+
+use crate::{
+    environment::Environment,
+    error::Source,
+    interpreter::Interpreter,
+    token::TType,
+    types::Type,
+    vm::{chunk::Proto, Vm},
+};
+
+use super::traits::{Call, FResult};
+
+// functions compiled to bytecode, run by the VM
+#[derive(Clone)]
+pub struct CompiledFn {
+    pub proto: Rc<Proto>,
+    // in the order declared, which is the order the resolver gave them slots in
+    pub optional_args: Vec<(String, Type)>,
+    pub closure: Environment,
+    pub source: Option<Rc<Source>>,
+}
+
+impl CompiledFn {
+    pub fn new(
+        proto: Rc<Proto>,
+        optional_args: Vec<(String, Type)>,
+        closure: Environment,
+        source: Option<Rc<Source>>,
+    ) -> Self {
+        Self { proto, optional_args, closure, source }
+    }
+}
+
+impl Call for CompiledFn {
+    fn arity(&self) -> usize {
+        self.proto.args.len()
+    }
+
+    // only the tree-walker calls compiled functions this way; the VM calls them itself
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Type>, opt_args: HashMap<String, Type>) -> FResult {
+        Vm::new(interpreter).call(self, args, opt_args)
+    }
+
+    fn to_string(&self) -> String {
+        match &self.proto.name.ttype {
+            TType::Identifier(s) => format!("<User Fn {}>", s),
+            // lambdas are named by the `fn` or `|` token that starts them
+            _ => format!(
+                "<Lambda Fn {}:{}>",
+                self.proto.name.lineinfo.line, self.proto.name.lineinfo.col
+            ),
+        }
+    }
+
+    fn name(&self) -> String {
+        match &self.proto.name.ttype {
+            TType::Identifier(name) => name.clone(),
+            _ => "<lambda>".into(),
+        }
+    }
+}
+
+impl Debug for CompiledFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Native Function")
+    }
+}
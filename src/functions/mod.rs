@@ -3,11 +3,13 @@ use crate::{interpreter::Interpreter, types::Type};
 use std::{fmt::{self, Display}, collections::HashMap};
 
 pub use self::{
+    compiled::CompiledFn,
     native::Func,
     traits::{Call, FResult},
     user::FuncCallable,
 };
 
+mod compiled;
 mod native;
 mod traits;
 mod user;
@@ -16,6 +18,7 @@ mod user;
 pub enum FuncType {
     Native(Func),
     User(FuncCallable),
+    Compiled(CompiledFn),
 }
 
 impl Call for FuncType {
@@ -23,6 +26,7 @@ impl Call for FuncType {
         match self {
             Self::Native(n) => n.arity(),
             Self::User(n) => n.arity(),
+            Self::Compiled(n) => n.arity(),
         }
     }
 
@@ -30,6 +34,7 @@ impl Call for FuncType {
         match self {
             Self::Native(n) => n.call(interpreter, args, opt_args),
             Self::User(n) => n.call(interpreter, args, opt_args),
+            Self::Compiled(n) => n.call(interpreter, args, opt_args),
         }
     }
 
@@ -37,6 +42,7 @@ impl Call for FuncType {
         match self {
             Self::Native(n) => n.to_string(),
             Self::User(n) => n.to_string(),
+            Self::Compiled(n) => n.to_string(),
         }
     }
 
@@ -44,6 +50,7 @@ impl Call for FuncType {
         match self {
             Self::Native(n) => n.name(),
             Self::User(n) => n.name(),
+            Self::Compiled(n) => n.name(),
         }
     }
 }
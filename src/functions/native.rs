@@ -24,6 +24,10 @@ impl Func {
             args,
         }
     }
+
+    pub fn rename(&mut self, name: String) {
+        self.name = name;
+    }
 }
 
 impl Call for Func {
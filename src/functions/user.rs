@@ -1,11 +1,12 @@
-use std::{fmt::Debug, collections::HashMap};
+use std::{fmt::Debug, collections::HashMap, mem, rc::Rc};
 
 use crate::{
-    interpreter::Interpreter,
-    nodes::stmt::Stmt,
-    token::{TType, Token},
+    environment::{Environment, Locals},
+    error::Source,
+    interpreter::{Interpreter, Unwind},
+    nodes::stmt::FnDecl,
+    token::TType,
     types::Type,
-    error::ErrorType
 };
 
 use super::traits::{Call, FResult};
@@ -13,34 +14,46 @@ use super::traits::{Call, FResult};
 // user-defined functions
 #[derive(Clone)]
 pub struct FuncCallable {
-    name: Token,
-    args: Vec<Token>,
-    optional_args: HashMap<String, Type>,
-    block: Vec<Stmt>,
+    // shared with the tree, and every other function made from the same declaration
+    decl: Rc<FnDecl>,
+    // in the order declared, which is the order the resolver gave them slots in
+    optional_args: Vec<(String, Type)>,
+    closure: Environment,
+    // resolved variable slots and source file of the code that defined the function
+    locals: Rc<Locals>,
+    source: Option<Rc<Source>>,
 }
 
 impl FuncCallable {
-    pub fn new(name: Token, args: Vec<Token>, optional_args: HashMap<String, Type>, block: Vec<Stmt>) -> Self {
-        Self { name, args, optional_args, block }
-    }
-}
-
-impl Call for FuncCallable {
-    fn arity(&self) -> usize {
-        self.args.len()
+    pub fn new(
+        decl: Rc<FnDecl>,
+        optional_args: Vec<(String, Type)>,
+        closure: Environment,
+        locals: Rc<Locals>,
+        source: Option<Rc<Source>>,
+    ) -> Self {
+        Self { decl, optional_args, closure, locals, source }
     }
 
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Type>, opt_args: HashMap<String, Type>) -> FResult {
+    /// Run the body in the environment the function was defined in, not the caller's.
+    fn run(
+        &self,
+        interpreter: &mut Interpreter,
+        args: Vec<Type>,
+        opt_args: HashMap<String, Type>,
+    ) -> Result<Type, Unwind> {
+        interpreter.environ = self.closure.clone();
+        interpreter.locals = self.locals.clone();
+        interpreter.source = self.source.clone();
         interpreter.environ.push_scope();
-        
-        for (i, name) in self.args.iter().enumerate() {
+
+        for (i, name) in self.decl.args.iter().enumerate() {
             match &name.ttype {
-                TType::Identifier(n) => interpreter.environ.define(&n, &args[i]),
+                TType::Identifier(n) => interpreter.environ.define(n, &args[i]),
                 _ => panic!(),
             }
         }
 
-        
         for (name, val) in self.optional_args.iter() {
             interpreter.environ.define(name, match &opt_args.get(name) {
                 Some(t) => t,
@@ -48,46 +61,61 @@ impl Call for FuncCallable {
             });
         }
 
-        let out = interpreter.eval_block(&self.block, false);
-        interpreter.environ.pop_scope();
-
-        return match out {
-            Ok(v) => match v {
-                Some(v) => Ok(v),
-                _ => Ok(Type::Nil)
-            },
-            Err(e) => match e.error_type {
-                ErrorType::Return(v) => Ok(v),
-                _ => Err(e)
+        match interpreter.eval_block(&self.decl.body, false) {
+            Ok(_) => Ok(Type::Nil),
+            Err(Unwind::Return(_, v)) => Ok(v),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Call for FuncCallable {
+    fn arity(&self) -> usize {
+        self.decl.args.len()
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Type>, opt_args: HashMap<String, Type>) -> FResult {
+        let previous = interpreter.environ.clone();
+        let previous_locals = interpreter.locals.clone();
+        let previous_source = interpreter.source.clone();
+        let previous_tail_calls = mem::replace(&mut interpreter.tail_calls, true);
+
+        let mut out = self.run(interpreter, args, opt_args);
+        let mut source = self.source.clone();
+
+        // a call in tail position is made here, once the function making it has
+        // returned, so that recursing through one doesn't use up the Rust stack
+        while let Err(Unwind::TailCall(call)) = out {
+            let call = *call;
+            if let Some(frame) = interpreter.frames.last_mut() {
+                *frame = call.frame;
             }
-        };
 
-        // if let Err(e) = out {
-        //     if let ErrorType::Return(v) = e.error_type {
-        //         return Ok(v)
-        //     }
+            source = call.func.source.clone();
+            out = call.func.run(interpreter, call.args, call.opt_args);
+        }
 
-        //     return Err(e)
-        // }
+        interpreter.environ = previous;
+        interpreter.locals = previous_locals;
+        interpreter.source = previous_source;
+        interpreter.tail_calls = previous_tail_calls;
 
-        // Ok(Type::Nil)
+        out.map_err(|e| e.into_error().in_source(&source))
     }
 
     fn to_string(&self) -> String {
-        format!(
-            "<User Fn {}>",
-            match &self.name.ttype {
-                TType::Identifier(s) => s,
-                _ => panic!(),
-            }
-        )
-        .into()
+        let name = &self.decl.name;
+        match &name.ttype {
+            TType::Identifier(s) => format!("<User Fn {}>", s),
+            // lambdas are named by the `fn` or `|` token that starts them
+            _ => format!("<Lambda Fn {}:{}>", name.lineinfo.line, name.lineinfo.col),
+        }
     }
 
     fn name(&self) -> String {
-        match &self.name.ttype {
+        match &self.decl.name.ttype {
             TType::Identifier(name) => name.clone(),
-            _ => panic!(),
+            _ => "<lambda>".into(),
         }
     }
 }
@@ -1,45 +1,154 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    mem,
+    rc::Rc,
+};
 
 use crate::{
-    environment::Environment,
-    error::{Error, ErrorType, LineInfo},
+    environment::{Environment, Locals, Slot},
+    error::{Error, ErrorType, Frame, LineInfo, Source},
     functions::{Call, FuncCallable, FuncType},
+    loader::{Loader, Names},
     nodes::{
         expr::Expr,
-        stmt::{ImportType, Stmt},
+        pattern::Pattern,
+        stmt::{FnDecl, ImportType, Stmt},
+        NodeId,
     },
     stdlib::Stdlib,
     token::{TType, Token},
-    types::{array::Array, module::Module},
+    types::array::Array,
     types::{map::Map, Type},
+    vm::{compiler::Compiler, Vm},
 };
 
-type IResult = Result<Type, Error>;
-// type SResult = Result<(), Error>;
+type IResult = Result<Type, Unwind>;
+/// A function to call, with its arguments and optional arguments.
+type Callee = (FuncType, Vec<Type>, HashMap<String, Type>);
+
+thread_local! {
+    /// the lowest stack address calls can be made from, if the thread's stack is known
+    static STACK_LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Let calls on this thread use up to `size` bytes of stack below the caller.
+pub fn limit_stack(size: usize) {
+    let limit = stack_position().saturating_sub(size);
+    STACK_LIMIT.with(|cell| cell.set(Some(limit)));
+}
+
+/// Roughly how far down the stack this thread is. The stack grows downwards.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    &marker as *const u8 as usize
+}
+
+/// Why evaluation stopped early: a real error, or a `break`, `continue` or
+/// `return` making its way to the loop or function that handles it.
+#[derive(Debug)]
+pub enum Unwind {
+    Error(Error),
+    Break(LineInfo),
+    Continue(LineInfo),
+    Return(LineInfo, Type),
+    /// a `return` of a call, which the function returning makes in its place
+    TailCall(Box<TailCall>),
+}
+
+/// A call to make once the function it is returned from has finished, so
+/// that it doesn't run inside it.
+#[derive(Debug)]
+pub struct TailCall {
+    pub func: FuncCallable,
+    pub args: Vec<Type>,
+    pub opt_args: HashMap<String, Type>,
+    /// replaces the frame of the function returning
+    pub frame: Frame,
+}
+
+impl Unwind {
+    /// The error for an unwind that reached the top of a program or function,
+    /// which for control flow means it was used outside a loop or function.
+    pub fn into_error(self) -> Error {
+        match self {
+            Unwind::Error(e) => e,
+            Unwind::Break(info) => Error::new(
+                info,
+                "Break statements can only be inside loops.".into(),
+                ErrorType::SyntaxError,
+            ),
+            Unwind::Continue(info) => Error::new(
+                info,
+                "Continue statements can only be inside loops.".into(),
+                ErrorType::SyntaxError,
+            ),
+            Unwind::Return(info, _) => Error::new(
+                info,
+                "Return statements can only be inside functions.".into(),
+                ErrorType::SyntaxError,
+            ),
+            // only a `return` makes a tail call
+            Unwind::TailCall(call) => Error::new(
+                call.frame.call,
+                "Return statements can only be inside functions.".into(),
+                ErrorType::SyntaxError,
+            ),
+        }
+    }
+}
+
+impl From<Error> for Unwind {
+    fn from(e: Error) -> Self {
+        Unwind::Error(e)
+    }
+}
 
 #[derive(Clone)]
 pub struct Interpreter {
-    pub nodes: Vec<Stmt>,
+    pub nodes: Rc<Vec<Stmt>>,
     pub environ: Environment,
-    pub locals: HashMap<LineInfo, usize>,
+    pub locals: Rc<Locals>,
     pub stdlib: Stdlib,
+    pub loader: Loader,
+    /// the imported file being run, if this is not the main program
+    pub source: Option<Rc<Source>>,
+    /// the function calls in progress, outermost first
+    pub frames: Vec<Frame>,
+    /// whether a `return` of a call can leave its function before making
+    /// the call, which it can't outside of functions or inside a `try`
+    pub tail_calls: bool,
 }
 
 impl Interpreter {
     // static methods
     pub fn new(nodes: Vec<Stmt>, environ: Environment) -> Self {
         Self {
-            nodes,
+            nodes: Rc::new(nodes),
             environ,
-            locals: HashMap::new(),
+            locals: Rc::new(Locals::default()),
             stdlib: Stdlib::new(),
+            loader: Loader::new(None, Vec::new()),
+            source: None,
+            frames: Vec::new(),
+            tail_calls: false,
+        }
+    }
+
+    /// Run the program, on the bytecode VM if the loader was asked to use it.
+    pub fn run(&mut self) -> Result<Type, Error> {
+        if !self.loader.vm {
+            return self.init();
         }
+
+        let chunk = Compiler::new(&self.locals).compile(&self.nodes);
+        Vm::new(self).run(chunk)
     }
 
-    pub fn init(&mut self) -> IResult {
+    pub fn init(&mut self) -> Result<Type, Error> {
         let mut eval = Type::Nil;
-        for stmt in self.nodes.clone() {
-            eval = self.eval_stmt(&stmt)?;
+        for stmt in self.nodes.clone().iter() {
+            eval = self.eval_stmt(stmt).map_err(Unwind::into_error)?;
         }
 
         Ok(eval)
@@ -50,9 +159,12 @@ impl Interpreter {
         match node {
             Stmt::ExprStmt(s) => self.eval_expr(s),
             Stmt::VarDecl(decls) => {
-                for (name, val) in decls {
+                for (pattern, val) in decls {
                     let val = self.eval_expr(&val)?;
-                    self.environ.define(&name, &val);
+
+                    for (tok, val) in self.destructure(pattern, &val)? {
+                        self.define(&tok, &val);
+                    }
                 }
                 Ok(Type::Nil)
             }
@@ -63,40 +175,62 @@ impl Interpreter {
             Stmt::IfStmt(cond, true_br, elif_brs, else_br) => {
                 Ok(self.eval_if(cond, true_br, elif_brs, else_br)?)
             }
-            Stmt::WhileStmt(cond, block) => {
-                loop {
-                    let cond = self.eval_expr(cond)?;
-                    if !self.is_truthy(&cond) {
-                        break;
-                    }
-
-                    let out = self.eval_block(block, false);
+            Stmt::WhileStmt(cond, block) => self.eval_loop(cond, block, true),
+            Stmt::DoWhile(block, cond) => self.eval_loop(cond, block, false),
+            Stmt::ForIn(names, tok, iterable, block) => {
+                let iterable = self.eval_expr(iterable)?;
+                let items = match iterable.iter() {
+                    Ok(items) => items,
+                    Err((msg, etype)) => return Err(Error::new(tok.lineinfo, msg, etype).into()),
+                };
 
-                    if let Err(e) = out {
-                        if e.error_type == ErrorType::Break {
-                            break;
+                for (key, value) in items {
+                    self.environ.push_scope();
+
+                    for (name, val) in names.iter().zip(match (names.len(), &iterable) {
+                        // a single variable walks the keys of a map, but the values of anything else
+                        (1, Type::Map(_)) => vec![key],
+                        (1, _) => vec![value],
+                        _ => vec![key, value],
+                    }) {
+                        match &name.ttype {
+                            TType::Identifier(n) => self.environ.define(n, &val),
+                            _ => panic!(),
                         }
+                    }
 
-                        if e.error_type == ErrorType::Continue {
-                            continue;
-                        }
+                    let out = self.eval_block(block, false);
+                    self.environ.pop_scope();
 
-                        return Err(e);
+                    match out {
+                        Ok(_) | Err(Unwind::Continue(_)) => {}
+                        Err(Unwind::Break(_)) => break,
+                        Err(e) => return Err(e),
                     }
                 }
 
                 Ok(Type::Nil)
             }
-            Stmt::Break(t) => Err(Error::new(
-                t.lineinfo,
-                "Break statements can only be inside loops.".into(),
-                ErrorType::Break,
-            )),
-            Stmt::Continue(t) => Err(Error::new(
-                t.lineinfo,
-                "Continue statements can only be inside loops.".into(),
-                ErrorType::Continue,
-            )),
+            Stmt::Break(t) => Err(Unwind::Break(t.lineinfo)),
+            Stmt::Continue(t) => Err(Unwind::Continue(t.lineinfo)),
+            Stmt::Return(t, Some(Expr::Call(func, tok, args, optional_args))) if self.tail_calls => {
+                let (func, args, opt_args) = self.callee(func, tok, args, optional_args)?;
+
+                let val = match func {
+                    FuncType::User(func) => {
+                        let frame = Frame {
+                            name: func.name(),
+                            call: tok.lineinfo,
+                            source: self.source.clone(),
+                        };
+
+                        return Err(Unwind::TailCall(Box::new(TailCall { func, args, opt_args, frame })));
+                    }
+                    func => self.call(func, tok, args, opt_args)?,
+                };
+
+                Err(Unwind::Return(t.lineinfo, val))
+            }
             Stmt::Return(t, val) => {
                 let expr;
                 if let Some(v) = val {
@@ -105,80 +239,56 @@ impl Interpreter {
                     expr = Type::Nil;
                 }
 
-                Err(Error::new(
-                    t.lineinfo,
-                    "Return statements can only be inside functions.".into(),
-                    ErrorType::Return(expr),
-                ))
+                Err(Unwind::Return(t.lineinfo, expr))
             }
-            Stmt::Function(name, args, optional_args, block) => {
-                let var_name = match &name.ttype {
-                    TType::Identifier(x) => x,
-                    _ => panic!(),
+            Stmt::Throw(t, val) => {
+                let val = self.eval_expr(val)?;
+                Err(Error::thrown(t.lineinfo, val).into())
+            }
+            Stmt::Try(body, catch, finally) => {
+                // the `catch` and `finally` have to run after any call returned
+                let tail_calls = mem::replace(&mut self.tail_calls, false);
+                let mut out = self.eval_block(body, false).map(|_| Type::Nil);
+                self.tail_calls = tail_calls && finally.is_none();
+
+                let caught = match (&out, catch) {
+                    // only errors are caught; control flow passes through
+                    (Err(Unwind::Error(e)), Some((name, handler))) => {
+                        Some((e.to_value(), name, handler))
+                    }
+                    _ => None,
                 };
 
-                let mut opt_args = HashMap::new();
+                if let Some((value, name, handler)) = caught {
+                    self.environ.push_scope();
+                    self.define(name, &value);
+                    out = self.eval_block(handler, false).map(|_| Type::Nil);
+                    self.environ.pop_scope();
+                }
+                self.tail_calls = tail_calls;
 
-                for (tok, expr) in optional_args {
-                    opt_args.insert(
-                        match &tok.ttype {
-                            TType::Identifier(name) => name.clone(),
-                            _ => panic!(),
-                        },
-                        self.eval_expr(expr)?,
-                    );
+                // an error in `finally` replaces the one being propagated
+                if let Some(finally) = finally {
+                    self.eval_block(finally, false)?;
                 }
 
-                self.environ.define(
-                    &var_name,
-                    &Type::Func(FuncType::User(FuncCallable::new(
-                        name.clone(),
-                        args.clone(),
-                        opt_args.clone(),
-                        block.clone(),
-                    ))),
-                );
+                out
+            }
+            Stmt::Function(decl) => {
+                let var_name = match &decl.name.ttype {
+                    TType::Identifier(x) => x,
+                    _ => panic!(),
+                };
+
+                let func = self.eval_fn(decl)?;
+                self.environ.define(var_name, &func);
 
                 Ok(Type::Nil)
             }
+            Stmt::Pub(stmt) => self.eval_stmt(stmt),
             Stmt::UseStmt(module, import_type) => {
-                if let TType::Identifier(name) = &module.ttype {
-                    let module = &self.stdlib.mods[name].fns;
-
-                    match &import_type {
-                        ImportType::Star => {
-                            for (name, func) in module {
-                                self.environ.define(name, &func.clone());
-                            }
-                        }
-
-                        ImportType::Multiple(fns) => {
-                            for fn_name in fns {
-                                let name_string = match &fn_name.ttype {
-                                    TType::Identifier(s) => s,
-                                    _ => panic!(),
-                                };
-
-                                let maybe_func = module.get(name_string);
-
-                                match maybe_func {
-                                    Some(func) => {
-                                        self.environ.define(name_string, &func.clone());
-                                    }
-                                    _ => {}
-                                }
-                            }
-                        }
-                        ImportType::Mod => self.environ.define(
-                            name,
-                            &Type::Module(Module::new(name.clone(), module.clone())),
-                        ),
-                    }
-
-                    Ok(Type::Nil)
-                } else {
-                    panic!();
-                }
+                self.import(module, import_type)?;
+                Ok(Type::Nil)
             }
         }
     }
@@ -214,30 +324,30 @@ impl Interpreter {
 
                 match tok.ttype {
                     TType::Not => Ok(Type::Bool(self.is_truthy(&rval))),
-                    TType::Minus => match rval {
-                        Type::Float(v) => Ok(Type::Float(-v)),
-                        _ => Err(Error::new(
-                            tok.lineinfo,
-                            "Only numbers can be negated.".into(),
-                            ErrorType::TypeError,
-                        )),
-                    },
+                    TType::Minus => rval
+                        .neg()
+                        .map_err(|(msg, etype)| Error::new(tok.lineinfo, msg, etype).into()),
                     _ => panic!(),
                 }
             }
-            Expr::Variable(v) => {
-                let some_key = self.locals.get(&v.lineinfo);
+            Expr::Variable(id, v) => match self.locals.get(*id) {
+                Some(slot) => Ok(self.environ.get(slot, v)?),
+                None => Err(Environment::undefined(v).into()),
+            },
+            Expr::Assign(id, k, v) => {
+                let val = self.eval_expr(&v)?;
 
-                if let Some(key) = some_key {
-                    Ok(self.environ.get_at(*key, v))
-                } else {
-                    self.environ.get(v)
-                }
+                Ok(self.assign(*id, k, &val)?)
             }
-            Expr::Assign(k, v) => {
-                let val = self.eval_expr(&v)?;
+            Expr::Destructure(pattern, ids, v) => {
+                let val = self.eval_expr(v)?;
 
-                self.assign(k, &val)
+                // matching binds in the same order as `Pattern::bindings`
+                for (id, (tok, val)) in ids.iter().zip(self.destructure(pattern, &val)?) {
+                    self.assign(*id, &tok, &val)?;
+                }
+
+                Ok(val)
             }
             Expr::Block(stmts) => Ok(self.eval_block(stmts, true)?.unwrap()),
             Expr::Logical(left, tok, right) => {
@@ -265,43 +375,8 @@ impl Interpreter {
                 Ok(self.eval_expr(else_br)?)
             }
             Expr::Call(func, tok, args, optional_args) => {
-                let callee = self.eval_expr(func)?;
-
-                let mut params: Vec<Type> = Vec::new();
-                let mut opt_params: HashMap<String, Type> = HashMap::new();
-                for arg in args {
-                    params.push(self.eval_expr(arg)?);
-                }
-
-                for (tok, expr) in optional_args {
-                    opt_params.insert(tok.clone(), self.eval_expr(expr)?);
-                }
-
-                if let Type::Func(func) = callee {
-                    let ar = func.arity();
-
-                    if params.len() != ar {
-                        return Err(Error::new(
-                            tok.lineinfo,
-                            format!(
-                                "Expected {} argument{}, but got {}.",
-                                ar,
-                                if ar == 1 { "" } else { "s" },
-                                params.len()
-                            )
-                            .into(),
-                            ErrorType::TypeError,
-                        ));
-                    }
-
-                    return func.call(self, params, opt_params);
-                } else {
-                    return Err(Error::new(
-                        tok.lineinfo,
-                        "Only functions can be called.".into(),
-                        ErrorType::TypeError,
-                    ));
-                }
+                let (func, params, opt_params) = self.callee(func, tok, args, optional_args)?;
+                self.call(func, tok, params, opt_params)
             }
             Expr::IfExpr(cond, true_br, elif_brs, else_br) => {
                 Ok(self.eval_if(cond, true_br, elif_brs, else_br)?)
@@ -310,7 +385,16 @@ impl Interpreter {
                 let v = self.eval_expr(val)?;
                 let k = self.eval_expr(key)?;
 
-                self.out(&v.index(k), &tok)
+                Ok(self.out(&v.index(k), tok)?)
+            }
+            Expr::Template(parts) => {
+                let mut out = String::new();
+
+                for part in parts {
+                    out += &self.eval_expr(part)?.to_string();
+                }
+
+                Ok(Type::String(out))
             }
             Expr::Array(itms) => {
                 let mut out: Vec<Type> = Vec::new();
@@ -325,28 +409,27 @@ impl Interpreter {
                 let left = self.eval_expr(left)?;
                 let right = self.eval_expr(right)?;
 
-                if let (Type::Float(l), Type::Float(r)) = (left, right) {
+                if let (Type::Int(l), Type::Int(r)) = (left, right) {
                     let mut out: Vec<Type> = Vec::new();
-                    let (l, r) = (l.floor() as i32, r.floor() as i32);
 
                     if l > r {
                         if *inclusive {
                             for i in (r..=l).rev() {
-                                out.push(Type::Float(i as f32));
+                                out.push(Type::Int(i));
                             }
                         } else {
                             for i in (r + 1..=l).rev() {
-                                out.push(Type::Float(i as f32));
+                                out.push(Type::Int(i));
                             }
                         }
                     } else {
                         if *inclusive {
                             for i in l..=r {
-                                out.push(Type::Float(i as f32));
+                                out.push(Type::Int(i));
                             }
                         } else {
                             for i in l..r {
-                                out.push(Type::Float(i as f32));
+                                out.push(Type::Int(i));
                             }
                         }
                     }
@@ -355,9 +438,9 @@ impl Interpreter {
                 } else {
                     Err(Error::new(
                         tok.lineinfo,
-                        "Ranges can only contain numbers.".into(),
+                        "Ranges can only contain integers.".into(),
                         ErrorType::TypeError,
-                    ))
+                    ).into())
                 }
             }
             Expr::Map(v) => {
@@ -372,12 +455,33 @@ impl Interpreter {
 
                 Ok(Type::Map(Rc::new(RefCell::new(Map::new(out)))))
             }
+            Expr::Lambda(decl) => self.eval_fn(decl),
+            Expr::Match(tok, value, arms) => {
+                let value = self.eval_expr(value)?;
+
+                for (pattern, guard, body) in arms {
+                    // bindings live in their own scope for the guard and body
+                    self.environ.push_scope();
+                    let out = self.eval_arm(&value, pattern, guard, body);
+                    self.environ.pop_scope();
+
+                    if let Some(out) = out? {
+                        return Ok(out);
+                    }
+                }
+
+                Err(Error::new(
+                    tok.lineinfo,
+                    format!("No pattern matched the value {}.", value),
+                    ErrorType::MatchError,
+                ).into())
+            }
             Expr::Set(var, brack, i, val) => {
                 let collection = self.eval_expr(var)?;
                 let i = self.eval_expr(i)?;
                 let val = self.eval_expr(val)?;
 
-                self.out(&collection.assign(i, val), brack)
+                Ok(self.out(&collection.assign(i, val), brack)?)
             }
             Expr::Prop(var, prop) => {
                 let module = self.eval_expr(var)?;
@@ -389,33 +493,45 @@ impl Interpreter {
                             _ => panic!(),
                         };
 
-                        let maybe_fn = module.fns.get(prop_string);
-
-                        if let Some(out) = maybe_fn {
-                            Ok(out.clone())
-                        } else {
-                            Err(Error::new(
-                                prop.lineinfo,
-                                format!(
-                                    "The item '{}' does not exist in the module '{}'.",
-                                    prop_string, module.name
-                                )
-                                .into(),
-                                ErrorType::TypeError,
-                            ))
+                        match module.get(prop_string) {
+                            Some(out) => Ok(out),
+                            None => Err(Names::of(&module).missing(prop, &module.name).into()),
                         }
                     }
                     _ => Err(Error::new(
                         prop.lineinfo,
                         "Only modules have properties.".into(),
                         ErrorType::TypeError,
-                    )),
+                    ).into()),
                 }
             }
         }
     }
 
-    pub fn eval_block(&mut self, block: &Vec<Stmt>, ret_val: bool) -> Result<Option<Type>, Error> {
+    fn eval_loop(&mut self, cond: &Expr, block: &Vec<Stmt>, check_first: bool) -> IResult {
+        let mut check = check_first;
+        loop {
+            if check {
+                let cond = self.eval_expr(cond)?;
+                if !self.is_truthy(&cond) {
+                    break;
+                }
+            }
+            check = true;
+
+            let out = self.eval_block(block, false);
+
+            match out {
+                Ok(_) | Err(Unwind::Continue(_)) => {}
+                Err(Unwind::Break(_)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Type::Nil)
+    }
+
+    pub fn eval_block(&mut self, block: &Vec<Stmt>, ret_val: bool) -> Result<Option<Type>, Unwind> {
         self.environ.push_scope();
 
         let mut val = Type::Nil;
@@ -476,6 +592,154 @@ impl Interpreter {
         Ok(Type::Nil)
     }
 
+    /// Evaluate the function and arguments of a call, checking it can be made.
+    fn callee(
+        &mut self,
+        func: &Expr,
+        tok: &Token,
+        args: &[Expr],
+        optional_args: &HashMap<String, Expr>,
+    ) -> Result<Callee, Unwind> {
+        let callee = self.eval_expr(func)?;
+
+        let mut params: Vec<Type> = Vec::new();
+        let mut opt_params: HashMap<String, Type> = HashMap::new();
+        for arg in args {
+            params.push(self.eval_expr(arg)?);
+        }
+
+        for (tok, expr) in optional_args {
+            opt_params.insert(tok.clone(), self.eval_expr(expr)?);
+        }
+
+        if let Type::Func(func) = callee {
+            let ar = func.arity();
+
+            if params.len() != ar {
+                return Err(Error::new(
+                    tok.lineinfo,
+                    format!(
+                        "Expected {} argument{}, but got {}.",
+                        ar,
+                        if ar == 1 { "" } else { "s" },
+                        params.len()
+                    ),
+                    ErrorType::TypeError,
+                ).into());
+            }
+
+            Ok((func, params, opt_params))
+        } else {
+            Err(Error::new(
+                tok.lineinfo,
+                "Only functions can be called.".into(),
+                ErrorType::TypeError,
+            ).into())
+        }
+    }
+
+    fn call(
+        &mut self,
+        func: FuncType,
+        tok: &Token,
+        params: Vec<Type>,
+        opt_params: HashMap<String, Type>,
+    ) -> IResult {
+        if let Some(error) = self.depth_error(tok.lineinfo) {
+            return Err(error.into());
+        }
+
+        self.frames.push(Frame {
+            name: func.name(),
+            call: tok.lineinfo,
+            source: self.source.clone(),
+        });
+
+        let out = match func {
+            FuncType::Native(_) => func
+                .call(self, params, opt_params)
+                .map_err(|e| e.at(tok.lineinfo)),
+            FuncType::User(_) | FuncType::Compiled(_) => {
+                func.call(self, params, opt_params)
+            }
+        }
+        .map_err(|e| e.with_trace(&self.frames));
+
+        self.frames.pop();
+        Ok(out?)
+    }
+
+    /// The error for a call that would nest more deeply than `--max-depth`
+    /// allows, or than the stack has room for.
+    pub fn depth_error(&self, line: LineInfo) -> Option<Error> {
+        let depth = self.frames.len();
+        let stack_left = STACK_LIMIT.with(Cell::get).is_none_or(|limit| stack_position() > limit);
+
+        if depth >= self.loader.max_depth {
+            Some(Error::new(
+                line,
+                format!("Calls can only be nested {} deep.", self.loader.max_depth),
+                ErrorType::RecursionError,
+            ))
+        } else if !stack_left {
+            Some(Error::new(
+                line,
+                format!("Calls nested {} deep ran out of stack.", depth),
+                ErrorType::RecursionError,
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn eval_fn(&mut self, decl: &Rc<FnDecl>) -> IResult {
+        let mut opt_args = Vec::new();
+
+        for (tok, expr) in &decl.optional_args {
+            opt_args.push((
+                match &tok.ttype {
+                    TType::Identifier(name) => name.clone(),
+                    _ => panic!(),
+                },
+                self.eval_expr(expr)?,
+            ));
+        }
+
+        Ok(Type::Func(FuncType::User(FuncCallable::new(
+            decl.clone(),
+            opt_args,
+            self.environ.clone(),
+            self.locals.clone(),
+            self.source.clone(),
+        ))))
+    }
+
+    fn eval_arm(
+        &mut self,
+        value: &Type,
+        pattern: &Pattern,
+        guard: &Option<Expr>,
+        body: &Expr,
+    ) -> Result<Option<Type>, Unwind> {
+        let mut bindings = Vec::new();
+        if !pattern.matches(value, &mut bindings) {
+            return Ok(None);
+        }
+
+        for (tok, val) in bindings {
+            self.define(&tok, &val);
+        }
+
+        if let Some(guard) = guard {
+            let cond = self.eval_expr(guard)?;
+            if !self.is_truthy(&cond) {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(self.eval_expr(body)?))
+    }
+
     // util
     fn out(&self, val: &Result<Type, (String, ErrorType)>, tok: &Token) -> Result<Type, Error> {
         match val {
@@ -484,7 +748,7 @@ impl Interpreter {
         }
     }
 
-    fn is_truthy(&self, v: &Type) -> bool {
+    pub fn is_truthy(&self, v: &Type) -> bool {
         match v {
             Type::Nil => false,
             Type::Bool(v) => *v,
@@ -492,19 +756,90 @@ impl Interpreter {
         }
     }
 
-    fn assign(&mut self, var: &Token, val: &Type) -> Result<Type, Error> {
-        let some_key = self.locals.get(&var.lineinfo);
+    pub fn define(&mut self, var: &Token, val: &Type) {
+        match &var.ttype {
+            TType::Identifier(name) => self.environ.define(name, val),
+            _ => panic!(),
+        }
+    }
 
-        if let Some(key) = some_key {
-            self.environ.assign_at(*key, var, val)?;
-        } else {
-            self.environ.assign(var, val)?;
+    /// The bindings of a pattern that `value` must match.
+    pub fn destructure(&self, pattern: &Pattern, value: &Type) -> Result<Vec<(Token, Type)>, Error> {
+        let mut bindings = Vec::new();
+
+        if pattern.matches(value, &mut bindings) {
+            return Ok(bindings);
+        }
+
+        let (tok, shape) = match pattern {
+            Pattern::Array(tok, ..) => (tok, "array"),
+            Pattern::Map(tok, _) => (tok, "map"),
+            _ => unreachable!(),
+        };
+
+        Err(Error::new(
+            tok.lineinfo,
+            format!("The value {} does not match the {} pattern.", value, shape),
+            ErrorType::MatchError,
+        ))
+    }
+
+    fn assign(&mut self, id: NodeId, var: &Token, val: &Type) -> Result<Type, Error> {
+        match self.locals.get(id) {
+            Some(slot) => self.environ.assign(slot, var, val)?,
+            None => return Err(Environment::undefined(var)),
         }
 
         Ok(val.clone())
     }
 
-    pub fn resolve(&mut self, tok: Token, depth: usize) {
-        self.locals.insert(tok.lineinfo, depth);
+    /// Load a module and define what `import_type` asks for from it.
+    pub fn import(&mut self, module: &Token, import_type: &ImportType) -> Result<(), Error> {
+        let name = Loader::module_name(module);
+
+        let module = match &module.ttype {
+            TType::Identifier(name) if self.stdlib.mods.contains_key(name) => {
+                self.stdlib.mods[name].clone()
+            }
+            _ => match self.loader.find(module) {
+                Some(path) => self.loader.load(module, &path)?,
+                None => return Err(self.loader.not_found(module, &self.stdlib)),
+            },
+        };
+
+        match import_type {
+            ImportType::Star => {
+                // in order of name, the order the resolver gave them slots in
+                let mut names: Vec<_> = module.fns.keys().collect();
+                names.sort();
+
+                for name in names {
+                    if let Some(val) = module.get(name) {
+                        self.environ.define(name, &val);
+                    }
+                }
+            }
+
+            ImportType::Multiple(fns) => {
+                for fn_name in fns {
+                    let name_string = match &fn_name.ttype {
+                        TType::Identifier(s) => s,
+                        _ => panic!(),
+                    };
+
+                    match module.get(name_string) {
+                        Some(func) => self.environ.define(name_string, &func),
+                        None => return Err(Names::of(&module).missing(fn_name, &name)),
+                    }
+                }
+            }
+            ImportType::Mod => self.environ.define(&name, &Type::Module(Rc::new(module))),
+        }
+
+        Ok(())
+    }
+
+    pub fn resolve(&mut self, id: NodeId, slot: Slot) {
+        Rc::make_mut(&mut self.locals).insert(id, slot);
     }
 }
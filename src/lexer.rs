@@ -1,9 +1,9 @@
 use crate::error::{Error, ErrorNote, ErrorType, LineInfo};
-use crate::token::{TType, Token};
+use crate::token::{TType, TemplatePart, Token};
 
 use maplit::hashmap;
 use std::char::from_u32 as char_from_u32;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct Lexer {
     code: String,
@@ -12,6 +12,8 @@ pub struct Lexer {
     info: LineInfo,
     tokens: Vec<Token>,
     keywords: HashMap<String, TType>,
+    /// lines with a `nowarn` comment, whose warnings aren't shown
+    pub silenced: HashSet<i32>,
 }
 
 impl Lexer {
@@ -27,6 +29,7 @@ impl Lexer {
                 "false".into() => TType::False,
                 "nil".into() => TType::Nil,
                 "fn".into() => TType::Fn,
+                "pub".into() => TType::Pub,
                 "return".into() => TType::Return,
                 "var".into() => TType::Var,
                 "use".into() => TType::Use,
@@ -41,7 +44,13 @@ impl Lexer {
                 "if".into() => TType::If,
                 "else".into() => TType::Else,
                 "elif".into() => TType::Elif,
+                "match".into() => TType::Match,
+                "throw".into() => TType::Throw,
+                "try".into() => TType::Try,
+                "catch".into() => TType::Catch,
+                "finally".into() => TType::Finally,
             },
+            silenced: HashSet::new(),
         }
     }
 
@@ -93,6 +102,8 @@ impl Lexer {
             '=' => {
                 if self.get('=') {
                     self.append_token(TType::EqEq)
+                } else if self.get('>') {
+                    self.append_token(TType::FatArrow)
                 } else {
                     self.append_token(TType::Eq)
                 }
@@ -125,70 +136,7 @@ impl Lexer {
 
                     if self.peek() == '\\' {
                         self.next();
-                        let ch = self.peek();
-                        self.next();
-
-                        str.push(match ch {
-                            'n' => '\n',
-                            'r' => '\r',
-                            't' => '\t',
-                            'a' => '\x07', // bell
-                            'b' => '\x08', // backspace
-                            'e' => '\x1b', // ansii escape
-                            'f' => '\x0c', // form feed
-                            'v' => '\x0b', // vertical tab
-                            '\\' => '\\',
-                            '\'' => '\'',
-                            '\"' => '\"',
-                            '?' => '?',
-                            'o' => u8::from_str_radix(self.read_n(3).as_str(), 8)
-                                .map_err(|_| ())
-                                .and_then(|c| char_from_u32(c as u32).ok_or(()))
-                                .map_err(|_| {
-                                    Error::new(
-                                        self.info,
-                                        "Invalid string escape.".into(),
-                                        ErrorType::SyntaxError,
-                                    )
-                                })?,
-                            'x' => u8::from_str_radix(self.read_n(2).as_str(), 16)
-                                .map_err(|_| ())
-                                .and_then(|c| char_from_u32(c as u32).ok_or(()))
-                                .map_err(|_| {
-                                    Error::new(
-                                        self.info,
-                                        "Invalid string escape.".into(),
-                                        ErrorType::SyntaxError,
-                                    )
-                                })?,
-                            'u' => u16::from_str_radix(self.read_n(4).as_str(), 16)
-                                .map_err(|_| ())
-                                .and_then(|c| char_from_u32(c as u32).ok_or(()))
-                                .map_err(|_| {
-                                    Error::new(
-                                        self.info,
-                                        "Invalid string escape.".into(),
-                                        ErrorType::SyntaxError,
-                                    )
-                                })?,
-                            'U' => u32::from_str_radix(self.read_n(8).as_str(), 16)
-                                .map_err(|_| ())
-                                .and_then(|c| char_from_u32(c).ok_or(()))
-                                .map_err(|_| {
-                                    Error::new(
-                                        self.info,
-                                        "Invalid string escape.".into(),
-                                        ErrorType::SyntaxError,
-                                    )
-                                })?,
-                            _ => {
-                                return Err(Error::new(
-                                    self.info,
-                                    "Invalid string escape.".into(),
-                                    ErrorType::SyntaxError,
-                                ))
-                            }
-                        });
+                        str.push(self.escape()?);
                     } else {
                         str.push(self.peek());
                         self.next();
@@ -196,12 +144,7 @@ impl Lexer {
                 }
 
                 if !self.is_valid() {
-                    return Err(Error::new_n(
-                        self.info,
-                        String::from("Unterminated string."),
-                        ErrorType::SyntaxError,
-                        vec![ErrorNote::Expect(lf, "String starts here.".into())],
-                    ));
+                    return Err(self.unterminated(lf));
                 }
 
                 self.next(); // "
@@ -211,6 +154,26 @@ impl Lexer {
                     lineinfo: self.info,
                 });
             }
+            '`' => {
+                let lf = self.info;
+                let mut fence = 1;
+
+                while self.peek() == '`' {
+                    self.next();
+                    fence += 1;
+                }
+
+                let ttype = if fence == 1 {
+                    self.template(lf)?
+                } else {
+                    self.raw_string(lf, fence)?
+                };
+
+                self.tokens.push(Token {
+                    ttype,
+                    lineinfo: self.info,
+                });
+            }
 
             ',' => self.append_token(TType::Comma),
             '.' => {
@@ -228,6 +191,9 @@ impl Lexer {
             '?' => self.append_token(TType::Question),
             ':' => self.append_token(TType::Colon),
 
+            // lambda parameters
+            '|' => self.append_token(TType::Pipe),
+
             // operators
             '+' => {
                 if self.get('=') {
@@ -267,11 +233,16 @@ impl Lexer {
                 if self.get('=') {
                     self.append_token(TType::DivideEq)
                 } else if self.get('/') {
+                    let start = self.i;
+
                     while self.peek() != '\n' && self.is_valid() {
                         self.next();
                     }
+
+                    self.comment(self.info, start);
                 } else if self.get('*') {
                     let lf = self.info;
+                    let start = self.i;
 
                     while self.is_valid() && !(self.peek() == '*' && self.peek_n(1) == '/') {
                         if self.peek() == '\n' {
@@ -290,6 +261,8 @@ impl Lexer {
                         ));
                     }
 
+                    self.comment(lf, start);
+
                     self.next(); // *
                     self.next() // /
                 } else {
@@ -320,31 +293,48 @@ impl Lexer {
                     }
                 } else if self.is_number(char) {
                     let mut num = String::from(char);
+                    let mut float = false;
 
-                    while self.is_valid() && (self.is_number(self.peek()) || self.peek() == '_') {
-                        let n = self.peek();
-                        if n != '_' {
-                            num += &n.to_string();
-                        }
-                        self.next();
-                    }
+                    self.digits(&mut num);
 
                     if self.peek() == '.' && self.is_number(self.peek_n(1)) {
                         num += &self.peek().to_string();
                         self.next(); // .
+                        self.digits(&mut num);
+                        float = true;
+                    }
 
-                        while self.is_valid() && (self.is_number(self.peek()) || self.peek() == '_')
-                        {
-                            let n = self.peek();
-                            if n != '_' {
-                                num += &n.to_string();
-                            }
-                            self.next();
+                    // exponent: 1e5, 2.5E-3
+                    if matches!(self.peek(), 'e' | 'E')
+                        && (self.is_number(self.peek_n(1))
+                            || matches!(self.peek_n(1), '+' | '-')
+                                && self.is_number(self.peek_n(2)))
+                    {
+                        num += &self.peek().to_string();
+                        self.next(); // e
+                        if !self.is_number(self.peek()) {
+                            num += &self.peek().to_string();
+                            self.next(); // sign
                         }
+                        self.digits(&mut num);
+                        float = true;
                     }
 
+                    // decimal suffix: 1.10d
+                    let ttype = if self.peek() == 'd' && !self.is_alphanum(self.peek_n(1)) {
+                        self.next(); // d
+                        TType::Decimal(num.parse().unwrap())
+                    } else if float {
+                        TType::Float(num.parse().unwrap())
+                    } else {
+                        match num.parse() {
+                            Ok(n) => TType::Int(n),
+                            Err(_) => TType::BigInt(num.parse().unwrap()),
+                        }
+                    };
+
                     self.tokens.push(Token {
-                        ttype: TType::Number(num.parse().unwrap()),
+                        ttype,
                         lineinfo: self.info,
                     });
                 } else {
@@ -359,6 +349,241 @@ impl Lexer {
         Ok(())
     }
 
+    /// Consume a run of digits and `_` separators into `num`.
+    fn digits(&mut self, num: &mut String) {
+        while self.is_valid() && (self.is_number(self.peek()) || self.peek() == '_') {
+            let n = self.peek();
+            if n != '_' {
+                num.push(n);
+            }
+            self.next();
+        }
+    }
+
+    /// A `` `template ${string}` ``. Text is split from the embedded
+    /// expressions, whose tokens are lexed in place so they keep their position.
+    fn template(&mut self, lf: LineInfo) -> Result<TType, Error> {
+        let mut parts = Vec::new();
+        let mut str = String::new();
+
+        while self.is_valid() && self.peek() != '`' {
+            match self.peek() {
+                '\\' => {
+                    self.next();
+                    str.push(self.escape()?);
+                }
+                '$' if self.peek_n(1) == '{' => {
+                    self.next(); // $
+                    self.next(); // {
+
+                    if !str.is_empty() {
+                        parts.push(TemplatePart::Str(std::mem::take(&mut str)));
+                    }
+                    parts.push(TemplatePart::Expr(self.interpolation(lf)?));
+                }
+                ch => {
+                    if ch == '\n' {
+                        self.newline();
+                    }
+                    str.push(ch);
+                    self.next();
+                }
+            }
+        }
+
+        if !self.is_valid() {
+            return Err(self.unterminated(lf));
+        }
+
+        self.next(); // `
+
+        if !str.is_empty() || parts.is_empty() {
+            parts.push(TemplatePart::Str(str));
+        }
+
+        Ok(TType::Template(parts))
+    }
+
+    /// The tokens of a `${ ... }`, up to its closing brace, followed by an EOF.
+    fn interpolation(&mut self, lf: LineInfo) -> Result<Vec<Token>, Error> {
+        let start = self.tokens.len();
+        let mut depth = 0;
+
+        loop {
+            if !self.is_valid() {
+                return Err(self.unterminated(lf));
+            }
+
+            match self.peek() {
+                '}' if depth == 0 => break,
+                // `}}` would lex as a single token, but here it closes a block and the `${`
+                '}' if depth == 1 && self.peek_n(1) == '}' => {
+                    self.next();
+                    self.append_token(TType::RightBrace);
+                    depth = 0;
+                }
+                _ => {
+                    let len = self.tokens.len();
+                    self.lex_char()?;
+
+                    if self.tokens.len() > len {
+                        depth += match self.tokens[len].ttype {
+                            TType::LeftBrace => 1,
+                            TType::LeftBBrace => 2,
+                            TType::RightBrace => -1,
+                            TType::RightBBrace => -2,
+                            _ => 0,
+                        };
+                    }
+                }
+            }
+        }
+
+        self.next(); // }
+
+        let mut tokens = self.tokens.split_off(start);
+        tokens.push(Token {
+            ttype: TType::EOF,
+            lineinfo: self.info,
+        });
+
+        Ok(tokens)
+    }
+
+    /// A string fenced by two or more backticks, taken as written up to the
+    /// next run of exactly as many backticks. As `` always opens one, there is
+    /// no empty template; the empty string is written "".
+    fn raw_string(&mut self, lf: LineInfo, fence: usize) -> Result<TType, Error> {
+        let mut str = String::new();
+
+        loop {
+            if !self.is_valid() {
+                let mut notes = vec![ErrorNote::Expect(lf, "String starts here.".into())];
+                if fence == 2 {
+                    notes.push(ErrorNote::Note(
+                        "Two backticks open a raw string; write an empty string as \"\".".into(),
+                    ));
+                }
+
+                return Err(Error::new_n(
+                    self.info,
+                    String::from("Unterminated string."),
+                    ErrorType::SyntaxError,
+                    notes,
+                ));
+            }
+
+            if self.peek() == '`' {
+                let mut run = 0;
+                while self.peek() == '`' {
+                    self.next();
+                    run += 1;
+                }
+
+                if run == fence {
+                    break;
+                }
+                str += &"`".repeat(run);
+            } else {
+                if self.peek() == '\n' {
+                    self.newline();
+                }
+                str.push(self.peek());
+                self.next();
+            }
+        }
+
+        Ok(TType::String(str))
+    }
+
+    /// Note a comment starting at `lf` whose text starts at `start`, e.g. `// nowarn`.
+    fn comment(&mut self, lf: LineInfo, start: usize) {
+        let text: String = self.chars[start..self.i].iter().collect();
+
+        if text.trim_start().starts_with("nowarn") {
+            self.silenced.insert(lf.line);
+        }
+    }
+
+    fn unterminated(&self, lf: LineInfo) -> Error {
+        Error::new_n(
+            self.info,
+            String::from("Unterminated string."),
+            ErrorType::SyntaxError,
+            vec![ErrorNote::Expect(lf, "String starts here.".into())],
+        )
+    }
+
+    /// Read the character after a `\` in a string.
+    fn escape(&mut self) -> Result<char, Error> {
+        let ch = self.peek();
+        self.next();
+
+        Ok(match ch {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'a' => '\x07', // bell
+            'b' => '\x08', // backspace
+            'e' => '\x1b', // ansii escape
+            'f' => '\x0c', // form feed
+            'v' => '\x0b', // vertical tab
+            '\\' => '\\',
+            '\'' => '\'',
+            '\"' => '\"',
+            '`' => '`',
+            '$' => '$',
+            '?' => '?',
+            'o' => u8::from_str_radix(self.read_n(3).as_str(), 8)
+                .map_err(|_| ())
+                .and_then(|c| char_from_u32(c as u32).ok_or(()))
+                .map_err(|_| {
+                    Error::new(
+                        self.info,
+                        "Invalid string escape.".into(),
+                        ErrorType::SyntaxError,
+                    )
+                })?,
+            'x' => u8::from_str_radix(self.read_n(2).as_str(), 16)
+                .map_err(|_| ())
+                .and_then(|c| char_from_u32(c as u32).ok_or(()))
+                .map_err(|_| {
+                    Error::new(
+                        self.info,
+                        "Invalid string escape.".into(),
+                        ErrorType::SyntaxError,
+                    )
+                })?,
+            'u' => u16::from_str_radix(self.read_n(4).as_str(), 16)
+                .map_err(|_| ())
+                .and_then(|c| char_from_u32(c as u32).ok_or(()))
+                .map_err(|_| {
+                    Error::new(
+                        self.info,
+                        "Invalid string escape.".into(),
+                        ErrorType::SyntaxError,
+                    )
+                })?,
+            'U' => u32::from_str_radix(self.read_n(8).as_str(), 16)
+                .map_err(|_| ())
+                .and_then(|c| char_from_u32(c).ok_or(()))
+                .map_err(|_| {
+                    Error::new(
+                        self.info,
+                        "Invalid string escape.".into(),
+                        ErrorType::SyntaxError,
+                    )
+                })?,
+            _ => {
+                return Err(Error::new(
+                    self.info,
+                    "Invalid string escape.".into(),
+                    ErrorType::SyntaxError,
+                ))
+            }
+        })
+    }
+
     // characters
     fn is_alpha(&self, char: char) -> bool {
         ('a' <= char && char <= 'z') || ('A' <= char && char <= 'Z') || char == '_'
@@ -0,0 +1,358 @@
+/*
+    The loader finds the `.eo` files imported with `use` and runs them.
+    Each file runs once per run in its own interpreter and environment, and its
+    top-level `pub` definitions become the items of a module.
+
+    Files are searched for in this order:
+    - the directory of the importing file
+    - every `--lib-path` directory, in the order given
+    - every directory in `EUROPA_PATH`
+    Paths starting with `./` or `../` are only looked up next to the importing file.
+*/
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fs, mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    environment::Environment,
+    error::{did_you_mean, Error, ErrorNote, ErrorType, Source},
+    interpreter::Interpreter,
+    lexer::Lexer,
+    optimiser::Optimiser,
+    nodes::stmt::{ImportType, Stmt},
+    parser::Parser,
+    resolver::Resolver,
+    stdlib::Stdlib,
+    token::{TType, Token},
+    types::module::Module,
+};
+
+/// The top-level names of a module.
+pub struct Names {
+    pub public: Vec<String>,
+    pub private: Vec<String>,
+}
+
+impl Names {
+    pub fn of(module: &Module) -> Self {
+        Self {
+            public: module.fns.keys().cloned().collect(),
+            private: module.private.iter().cloned().collect(),
+        }
+    }
+
+    /// The error for importing or accessing an item that the module does not export.
+    pub fn missing(&self, item: &Token, module: &str) -> Error {
+        let name = match &item.ttype {
+            TType::Identifier(name) => name,
+            _ => panic!(),
+        };
+
+        if self.private.contains(name) {
+            Error::new(
+                item.lineinfo,
+                format!("The item '{}' is private to the module '{}'.", name, module),
+                ErrorType::ReferenceError,
+            )
+        } else {
+            Error::new_n(
+                item.lineinfo,
+                format!("The item '{}' does not exist in the module '{}'.", name, module),
+                ErrorType::ReferenceError,
+                did_you_mean(name, &self.public).into_iter().collect(),
+            )
+        }
+    }
+}
+
+/// How deeply calls may nest by default, like Python's recursion limit.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+#[derive(Clone)]
+pub struct Loader {
+    /// the file doing the importing, if any
+    file: Option<PathBuf>,
+    /// directories searched after the importing file's own
+    lib_paths: Rc<Vec<PathBuf>>,
+    /// modules already run, shared by every loader of the run
+    cache: Rc<RefCell<HashMap<PathBuf, Module>>>,
+    /// files currently being loaded, outermost first
+    loading: Rc<RefCell<Vec<PathBuf>>>,
+    /// whether files are run on the bytecode VM rather than the tree-walker
+    pub vm: bool,
+    /// whether files are optimised after they are resolved
+    pub optimise: bool,
+    /// how deeply calls may nest before a `RecursionError`
+    pub max_depth: usize,
+    /// where `io.print` and `io.println` write, instead of standard output
+    pub output: Option<Rc<RefCell<String>>>,
+}
+
+impl Loader {
+    pub fn new(file: Option<&Path>, lib_paths: Vec<PathBuf>) -> Self {
+        let file = file.map(|file| fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf()));
+
+        Self {
+            loading: Rc::new(RefCell::new(file.iter().cloned().collect())),
+            file,
+            lib_paths: Rc::new(lib_paths),
+            cache: Rc::new(RefCell::new(HashMap::new())),
+            vm: false,
+            optimise: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            output: None,
+        }
+    }
+
+    /// A loader for imports made by `file`, sharing this loader's cache.
+    fn child(&self, file: &Path) -> Self {
+        Self {
+            file: Some(file.to_path_buf()),
+            ..self.clone()
+        }
+    }
+
+    /// The name a `use` target is bound to, e.g. `utils` for `use "./lib/utils.eo";`.
+    pub fn module_name(module: &Token) -> String {
+        match &module.ttype {
+            TType::Identifier(name) => name.clone(),
+            TType::String(path) => Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.clone()),
+            _ => panic!(),
+        }
+    }
+
+    /// The error for a `use` target that is neither in the stdlib nor on the search path.
+    pub fn not_found(&self, module: &Token, stdlib: &Stdlib) -> Error {
+        let name = Self::module_name(module);
+        let mut available = self.available();
+        available.extend(stdlib.mods.keys().cloned());
+
+        Error::new_n(
+            module.lineinfo,
+            format!("Module '{}' not found.", name),
+            ErrorType::ReferenceError,
+            did_you_mean(&name, &available).into_iter().collect(),
+        )
+    }
+
+    /// The names of every module file on the search path.
+    fn available(&self) -> Vec<String> {
+        self.dirs()
+            .iter()
+            .filter_map(|dir| {
+                if dir.as_os_str().is_empty() {
+                    fs::read_dir(".").ok()
+                } else {
+                    fs::read_dir(dir).ok()
+                }
+            })
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+
+                if path.extension()? == "eo" {
+                    Some(path.file_stem()?.to_string_lossy().into_owned())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn dirs(&self) -> Vec<PathBuf> {
+        let dir = match &self.file {
+            Some(file) => file.parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+
+        let mut dirs = vec![dir];
+        dirs.extend(self.lib_paths.iter().cloned());
+        dirs
+    }
+
+    /// Find the file a `use` target refers to.
+    pub fn find(&self, module: &Token) -> Option<PathBuf> {
+        let (path, relative) = match &module.ttype {
+            TType::Identifier(name) => (PathBuf::from(format!("{}.eo", name)), false),
+            TType::String(path) => (
+                PathBuf::from(path),
+                path.starts_with("./") || path.starts_with("../"),
+            ),
+            _ => panic!(),
+        };
+
+        let mut dirs = self.dirs();
+        if relative {
+            dirs.truncate(1);
+        }
+
+        dirs.into_iter()
+            .map(|dir| dir.join(&path))
+            .find(|path| path.is_file())
+            .map(|path| fs::canonicalize(&path).unwrap_or(path))
+    }
+
+    /// Run a module file and collect its top-level definitions, or reuse them
+    /// if the file has already been run.
+    pub fn load(&self, module: &Token, path: &Path) -> Result<Module, Error> {
+        if let Some(module) = self.cache.borrow().get(path) {
+            return Ok(module.clone());
+        }
+
+        self.enter(module, path)?;
+        let out = self.run(module, path);
+        self.loading.borrow_mut().pop();
+
+        let out = out?;
+        self.cache
+            .borrow_mut()
+            .insert(path.to_path_buf(), out.clone());
+
+        Ok(out)
+    }
+
+    fn run(&self, module: &Token, path: &Path) -> Result<Module, Error> {
+        let (nodes, source) = self.parse(module, path)?;
+        let source = Some(source);
+        let public = Self::scan(&nodes).public;
+
+        let mut interpreter = Interpreter::new(nodes, Environment::new());
+        interpreter.loader = self.child(path);
+        interpreter.source = source.clone();
+
+        Resolver::new(&mut interpreter)
+            .init()
+            .map_err(|e| e.in_source(&source))?;
+
+        if self.optimise {
+            interpreter.nodes = Optimiser::new().init(mem::take(&mut interpreter.nodes));
+        }
+
+        interpreter.run().map_err(|e| e.in_source(&source))?;
+
+        let (fns, private) = interpreter
+            .environ
+            .globals()
+            .into_iter()
+            .partition(|(name, _)| public.contains(name));
+
+        let mut out = Module::new(Self::module_name(module), fns);
+        out.private = private.into_keys().collect();
+        out.environ = Some(interpreter.environ);
+
+        Ok(out)
+    }
+
+    /// The names a module file defines at the top level, without running it.
+    pub fn names(&self, module: &Token, path: &Path) -> Result<Names, Error> {
+        if let Some(module) = self.cache.borrow().get(path) {
+            return Ok(Names::of(module));
+        }
+
+        self.enter(module, path)?;
+        let out = self.parse(module, path).map(|(nodes, _)| Self::scan(&nodes));
+        self.loading.borrow_mut().pop();
+
+        out
+    }
+
+    fn scan(nodes: &[Stmt]) -> Names {
+        let mut names = Names {
+            public: Vec::new(),
+            private: Vec::new(),
+        };
+
+        for node in nodes {
+            let (node, list) = match node {
+                Stmt::Pub(node) => (node.as_ref(), &mut names.public),
+                _ => (node, &mut names.private),
+            };
+
+            match node {
+                Stmt::Function(decl) => {
+                    if let TType::Identifier(name) = &decl.name.ttype {
+                        list.push(name.clone());
+                    }
+                }
+                Stmt::VarDecl(vars) => {
+                    for (pattern, _) in vars {
+                        list.extend(pattern.bindings().into_iter().map(Self::module_name));
+                    }
+                }
+                // imports are never re-exported
+                Stmt::UseStmt(inner, ImportType::Mod) => list.push(Self::module_name(inner)),
+                Stmt::UseStmt(_, ImportType::Multiple(items)) => {
+                    for item in items {
+                        list.push(Self::module_name(item));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        names
+    }
+
+    /// Mark `path` as being loaded, failing if that would close an import cycle.
+    fn enter(&self, module: &Token, path: &Path) -> Result<(), Error> {
+        let mut loading = self.loading.borrow_mut();
+
+        if let Some(start) = loading.iter().position(|file| file == path) {
+            let chain = loading[start..]
+                .iter()
+                .chain([path.to_path_buf()].iter())
+                .map(|file| Self::display(file))
+                .collect::<Vec<String>>()
+                .join(" -> ");
+
+            return Err(Error::new_n(
+                module.lineinfo,
+                format!("Cyclic import of '{}'.", Self::display(path)),
+                ErrorType::ImportError,
+                vec![ErrorNote::Note(format!("Import chain: {}", chain))],
+            ));
+        }
+
+        loading.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn parse(&self, module: &Token, path: &Path) -> Result<(Vec<Stmt>, Rc<Source>), Error> {
+        let code = fs::read_to_string(path).map_err(|err| {
+            Error::new(
+                module.lineinfo,
+                format!("Could not read module '{}': {}", Self::display(path), err),
+                ErrorType::ImportError,
+            )
+        })?;
+
+        let source = Rc::new(Source {
+            path: Self::display(path),
+            code,
+        });
+        let in_source = |e: Error| e.in_source(&Some(source.clone()));
+
+        let tokens = Lexer::new(&source.code).init().map_err(in_source)?;
+        let nodes = Parser::new(tokens).init().map_err(in_source)?;
+
+        Ok((nodes, source))
+    }
+
+    /// Paths are canonical, so show them relative to the working directory when possible.
+    fn display(path: &Path) -> String {
+        env::current_dir()
+            .ok()
+            .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+            .unwrap_or_else(|| path.to_path_buf())
+            .display()
+            .to_string()
+    }
+}
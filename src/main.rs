@@ -5,7 +5,9 @@ mod error;
 mod functions;
 mod interpreter;
 mod lexer;
+mod loader;
 mod nodes;
+mod optimiser;
 mod parser;
 mod repl;
 mod resolver;
@@ -13,12 +15,16 @@ mod tests;
 mod token;
 mod types;
 mod stdlib;
+mod vm;
 
+use std::path::{Path, PathBuf};
 use std::time::Instant;
-use std::{env, fs, process};
+use std::{env, fs, mem, panic, process, thread};
 
 use interpreter::Interpreter;
 use lexer::Lexer;
+use loader::{Loader, DEFAULT_MAX_DEPTH};
+use optimiser::Optimiser;
 use parser::Parser;
 use resolver::Resolver;
 
@@ -26,13 +32,21 @@ use crate::environment::Environment;
 use crate::error::Error;
 use crate::types::Type;
 
-use clap::{App, Arg};
+use clap::{value_t, App, Arg, ArgMatches};
 
 const PROGRAM_NAME: &str = env!("CARGO_PKG_NAME");
 const PROGRAM_VERSION: &str = env!("CARGO_PKG_VERSION");
 const PROGRAM_AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const PROGRAM_ABOUT: &str = env!("CARGO_PKG_DESCRIPTION");
 
+/// Rust stack for the interpreter's thread. The tree-walker recurses on it for
+/// every call that isn't a tail call (much more deeply without optimisations),
+/// so a large `--max-depth` can run out of it first, raising a RecursionError.
+const STACK_SIZE: usize = if cfg!(debug_assertions) { 512 } else { 256 } * 1024 * 1024;
+
+/// Stack left unused by calls, for the last call itself and native code.
+const STACK_RESERVE: usize = 16 * 1024 * 1024;
+
 fn main() {
     let matches = App::new(PROGRAM_NAME)
         .version(PROGRAM_VERSION)
@@ -59,10 +73,87 @@ fn main() {
                 .takes_value(true)
                 .conflicts_with("FILE"),
         )
+        .arg(
+            Arg::with_name("lib-path")
+                .short("L")
+                .long("lib-path")
+                .value_name("DIR")
+                .help("Search DIR for imported modules (before EUROPA_PATH)")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("deny-warnings")
+                .long("deny-warnings")
+                .help("Stop at the first warning, as if it were an error"),
+        )
+        .arg(
+            Arg::with_name("optimise")
+                .short("O")
+                .long("optimise")
+                .help("Fold constant expressions and remove branches that can never run"),
+        )
+        .arg(
+            Arg::with_name("vm")
+                .long("vm")
+                .help("Compile to bytecode and run it on the VM, instead of walking the tree"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .help("Raise a RecursionError when calls are nested more than N deep [default: 1000]")
+                .takes_value(true),
+        )
         .arg(Arg::with_name("FILE").help("File to run").index(1))
         .get_matches();
 
+    let max_depth = if matches.is_present("max-depth") {
+        value_t!(matches, "max-depth", usize).unwrap_or_else(|e| e.exit())
+    } else {
+        DEFAULT_MAX_DEPTH
+    };
+
+    with_stack(move || start(matches, max_depth));
+}
+
+/// Run `f` on a thread with a large stack, which calls know the size of.
+pub fn with_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            interpreter::limit_stack(STACK_SIZE - STACK_RESERVE);
+            f()
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Error starting the interpreter: {}", err);
+            process::exit(1)
+        })
+        .join()
+        .unwrap_or_else(|err| panic::resume_unwind(err))
+}
+
+fn start(matches: ArgMatches, max_depth: usize) {
+
     let verbose = matches.is_present("verbose");
+    let deny_warnings = matches.is_present("deny-warnings");
+
+    // module search path, after the importing file's own directory
+    let mut lib_paths: Vec<PathBuf> = matches
+        .values_of("lib-path")
+        .map(|dirs| dirs.map(PathBuf::from).collect())
+        .unwrap_or_default();
+
+    if let Some(paths) = env::var_os("EUROPA_PATH") {
+        lib_paths.extend(env::split_paths(&paths));
+    }
+
+    let file = matches.value_of("FILE").map(Path::new);
+    let mut loader = Loader::new(file, lib_paths);
+    loader.vm = matches.is_present("vm");
+    loader.optimise = matches.is_present("optimise");
+    loader.max_depth = max_depth;
 
     let code = if let Some(file) = matches.value_of("FILE") {
         // run file contents
@@ -82,14 +173,14 @@ fn main() {
 
         // start no-context repl
         let environ = Environment::new();
-        repl::init(environ, verbose);
+        repl::init(environ, loader, verbose);
 
         return;
     };
 
     // load and run code
     let mut environ = Environment::new();
-    match run_string(&code, &mut environ, verbose) {
+    match run_string(&code, &loader, &mut environ, verbose, deny_warnings) {
         Err(e) => {
             e.display(&code);
             process::exit(1);
@@ -99,7 +190,7 @@ fn main() {
                 println!("{:?}", eval);
 
                 // drop into repl with environment
-                repl::init(environ, verbose);
+                repl::init(environ, loader, verbose);
             }
         }
     }
@@ -108,12 +199,15 @@ fn main() {
 // Loader for code, mutates Environment and returns evaluated (probably Nil)
 fn run_string(
     code: &String,
+    loader: &Loader,
     environ: &mut Environment,
     verbose: bool,
+    deny_warnings: bool,
 ) -> Result<Type, Error> {
     // Tokenise code
     let mut time = Instant::now();
-    let tokens = Lexer::new(&code).init()?;
+    let mut lexer = Lexer::new(code);
+    let tokens = lexer.init()?;
 
     if verbose {
         eprintln!("lexer {:?}", time.elapsed());
@@ -129,18 +223,45 @@ fn run_string(
 
     // Create interpreter
     let mut interpreter = Interpreter::new(tree, environ.clone());
+    interpreter.loader = loader.clone();
 
     // Resolve variables
     time = Instant::now();
-    interpreter = Resolver::new(interpreter).init()?;
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.silenced = lexer.silenced;
+    resolver.init()?;
 
     if verbose {
         eprintln!("resolver {:?}", time.elapsed());
     }
 
+    if deny_warnings && !resolver.warnings.is_empty() {
+        return Err(resolver.warnings.remove(0));
+    }
+
+    for warning in &resolver.warnings {
+        warning.display(code);
+    }
+
+    // Simplify AST, after resolving so that code it removes is still checked
+    if loader.optimise {
+        time = Instant::now();
+        let mut optimiser = Optimiser::new();
+        interpreter.nodes = optimiser.init(mem::take(&mut interpreter.nodes));
+
+        if verbose {
+            eprintln!(
+                "optimiser {:?} ({} folded, {} removed)",
+                time.elapsed(),
+                optimiser.folded,
+                optimiser.removed
+            );
+        }
+    }
+
     // Run interpreter
     time = Instant::now();
-    let eval = interpreter.init()?;
+    let eval = interpreter.run()?;
 
     if verbose {
         eprintln!("interpreter {:?}", time.elapsed());
@@ -1,18 +1,24 @@
 use std::collections::HashMap;
 use std::rc::Rc;
 
-use crate::nodes::stmt::Stmt;
+use crate::nodes::{
+    pattern::Pattern,
+    stmt::{FnDecl, Stmt},
+    NodeId,
+};
 use crate::token::Token;
 use crate::types::Type;
 
 #[derive(Clone, Debug)]
 pub enum Expr {
-    Assign(Token, Rc<Expr>),
+    Assign(NodeId, Token, Rc<Expr>),
+    // one id for each of the pattern's bindings, in `Pattern::bindings` order
+    Destructure(Rc<Pattern>, Vec<NodeId>, Rc<Expr>),
     Binary(Rc<Expr>, Token, Rc<Expr>),
     Grouping(Rc<Expr>),
     Literal(Type),
     Unary(Token, Rc<Expr>),
-    Variable(Token),
+    Variable(NodeId, Token),
     Block(Vec<Stmt>),
     Logical(Rc<Expr>, Token, Rc<Expr>),
     Ternary(Rc<Expr>, Rc<Expr>, Rc<Expr>),
@@ -22,6 +28,9 @@ pub enum Expr {
     Set(Rc<Expr>, Token, Rc<Expr>, Rc<Expr>),
     Prop(Rc<Expr>, Token),
     Array(Vec<Expr>),
+    Template(Vec<Expr>),
     Map(Vec<(Expr, Expr)>),
     Range(Rc<Expr>, Token, Rc<Expr>, bool),
+    Lambda(Rc<FnDecl>),
+    Match(Token, Rc<Expr>, Vec<(Pattern, Option<Expr>, Expr)>),
 }
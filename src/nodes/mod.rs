@@ -1,2 +1,7 @@
 pub mod expr;
-pub mod stmt;
\ No newline at end of file
+pub mod pattern;
+pub mod stmt;
+
+/// Identifies an expression that refers to a variable, so the resolver can
+/// tell the interpreter which scope to find it in.
+pub type NodeId = usize;
@@ -0,0 +1,94 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    token::Token,
+    types::{array::Array, Type},
+};
+
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    Wildcard,
+    Literal(Type),
+    Range(Type, Type, bool),
+    Binding(Token),
+    // the optional rest is the index it appears at and the name it binds
+    Array(Token, Vec<Pattern>, Option<(usize, Option<Token>)>),
+    Map(Token, Vec<(String, Pattern)>),
+}
+
+impl Pattern {
+    /// every variable the pattern binds
+    pub fn bindings(&self) -> Vec<&Token> {
+        match self {
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Range(..) => vec![],
+            Pattern::Binding(tok) => vec![tok],
+            Pattern::Array(_, items, rest) => {
+                let mut out: Vec<&Token> = items.iter().flat_map(Pattern::bindings).collect();
+
+                if let Some((_, Some(tok))) = rest {
+                    out.push(tok);
+                }
+
+                out
+            }
+            Pattern::Map(_, items) => items.iter().flat_map(|(_, p)| p.bindings()).collect(),
+        }
+    }
+
+    /// Check `value` against the pattern, collecting the values of its bindings.
+    pub fn matches(&self, value: &Type, bindings: &mut Vec<(Token, Type)>) -> bool {
+        match (self, value) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Literal(lit), _) => lit == value,
+            (Pattern::Range(start, end, inclusive), _) if value.is_number() => {
+                value >= start && if *inclusive { value <= end } else { value < end }
+            }
+            (Pattern::Binding(tok), _) => {
+                bindings.push((tok.clone(), value.clone()));
+                true
+            }
+            (Pattern::Array(_, items, rest), Type::Array(arr)) => {
+                let arr = arr.borrow().arr.clone();
+
+                match rest {
+                    None => {
+                        arr.len() == items.len()
+                            && items
+                                .iter()
+                                .zip(arr.iter())
+                                .all(|(p, v)| p.matches(v, bindings))
+                    }
+                    Some((i, name)) => {
+                        if arr.len() < items.len() {
+                            return false;
+                        }
+
+                        // items before the rest match the front, items after it match the back
+                        let end = arr.len() - (items.len() - i);
+                        let matched = items[..*i]
+                            .iter()
+                            .zip(arr[..*i].iter())
+                            .chain(items[*i..].iter().zip(arr[end..].iter()))
+                            .all(|(p, v)| p.matches(v, bindings));
+
+                        if let Some(tok) = name {
+                            let rest = Array::new(arr[*i..end].to_vec());
+                            bindings.push((tok.clone(), Type::Array(Rc::new(RefCell::new(rest)))));
+                        }
+
+                        matched
+                    }
+                }
+            }
+            (Pattern::Map(_, items), Type::Map(map)) => {
+                let map = map.borrow().map.clone();
+
+                items.iter().all(|(key, p)| match map.get(key) {
+                    Some(v) => p.matches(v, bindings),
+                    None => false,
+                })
+            }
+            _ => false,
+        }
+    }
+}
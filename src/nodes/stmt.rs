@@ -1,17 +1,38 @@
-use crate::{nodes::expr::Expr, token::Token};
+use std::rc::Rc;
+
+use crate::{
+    nodes::{expr::Expr, pattern::Pattern},
+    token::Token,
+};
 
 #[derive(Clone, Debug)]
 pub enum Stmt {
     ExprStmt(Expr),
-    VarDecl(Vec<(String, Expr)>),
+    VarDecl(Vec<(Pattern, Expr)>),
     Block(Vec<Stmt>),
     IfStmt(Expr, Vec<Stmt>, Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>),
     WhileStmt(Expr, Vec<Stmt>),
+    DoWhile(Vec<Stmt>, Expr),
+    ForIn(Vec<Token>, Token, Expr, Vec<Stmt>),
     Break(Token),
     Continue(Token),
     Return(Token, Option<Expr>),
-    Function(Token, Vec<Token>, Vec<(Token, Expr)>, Vec<Stmt>),
+    Throw(Token, Expr),
+    Try(Vec<Stmt>, Option<(Token, Vec<Stmt>)>, Option<Vec<Stmt>>),
+    Function(Rc<FnDecl>),
     UseStmt(Token, ImportType),
+    Pub(Rc<Stmt>),
+}
+
+/// The parameters and body of a function, shared by every function value
+/// made from it.
+#[derive(Clone, Debug)]
+pub struct FnDecl {
+    /// for a lambda, the `fn` or `|` token that starts it
+    pub name: Token,
+    pub args: Vec<Token>,
+    pub optional_args: Vec<(Token, Expr)>,
+    pub body: Vec<Stmt>,
 }
 
 #[derive(Clone, Debug)]
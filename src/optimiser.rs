@@ -0,0 +1,320 @@
+/*
+    The optimiser rewrites the tree between resolving and running. It folds
+    operators whose operands are literals into a single literal, and removes
+    `if` branches and `while` loops whose conditions are constant. Running
+    after the resolver means code it removes still has its errors and
+    warnings reported.
+
+    An operation that would fail at runtime (like `1 / 0`) is left as it is,
+    so its error is still raised at the operator, when it is run.
+*/
+
+use std::{iter, rc::Rc};
+
+use crate::{
+    nodes::{
+        expr::Expr,
+        stmt::{FnDecl, Stmt},
+    },
+    token::{TType, Token},
+    types::{ops::TResult, Type},
+};
+
+type Branches = (Vec<(Expr, Vec<Stmt>)>, Option<Vec<Stmt>>);
+
+#[derive(Default)]
+pub struct Optimiser {
+    /// expressions replaced by their value
+    pub folded: usize,
+    /// branches and loops removed because they could never run
+    pub removed: usize,
+}
+
+impl Optimiser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn init(&mut self, nodes: Rc<Vec<Stmt>>) -> Rc<Vec<Stmt>> {
+        Rc::new(self.stmts(unwrap(nodes)))
+    }
+
+    // stmts
+    fn stmts(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let len = stmts.len();
+        let mut out = Vec::with_capacity(len);
+
+        for (i, stmt) in stmts.into_iter().enumerate() {
+            match self.stmt(stmt) {
+                Some(stmt) => out.push(stmt),
+                // a block used as an expression is worth its last statement, which
+                // gave nil, so keep that rather than take the value of the one before
+                None if i == len - 1 => out.push(Stmt::ExprStmt(Expr::Literal(Type::Nil))),
+                None => {}
+            }
+        }
+
+        out
+    }
+
+    /// The optimised statement, or `None` if it does nothing.
+    fn stmt(&mut self, node: Stmt) -> Option<Stmt> {
+        Some(match node {
+            Stmt::ExprStmt(expr) => Stmt::ExprStmt(self.expr(expr)),
+            Stmt::VarDecl(decls) => Stmt::VarDecl(
+                decls
+                    .into_iter()
+                    .map(|(pattern, val)| (pattern, self.expr(val)))
+                    .collect(),
+            ),
+            Stmt::Block(stmts) => Stmt::Block(self.stmts(stmts)),
+            Stmt::IfStmt(cond, true_br, elif_brs, else_br) => {
+                match self.branches(cond, true_br, elif_brs, else_br) {
+                    (branches, None) if branches.is_empty() => return None,
+                    // the `if` is only there for its scope and value now
+                    (branches, Some(block)) if branches.is_empty() => {
+                        Stmt::ExprStmt(Expr::Block(block))
+                    }
+                    (mut branches, else_br) => {
+                        let (cond, true_br) = branches.remove(0);
+                        Stmt::IfStmt(cond, true_br, branches, else_br)
+                    }
+                }
+            }
+            Stmt::WhileStmt(cond, block) => {
+                let cond = self.expr(cond);
+
+                if constant(&cond) == Some(false) {
+                    self.removed += 1;
+                    return None;
+                }
+
+                Stmt::WhileStmt(cond, self.stmts(block))
+            }
+            Stmt::DoWhile(block, cond) => Stmt::DoWhile(self.stmts(block), self.expr(cond)),
+            Stmt::ForIn(names, tok, iterable, block) => {
+                Stmt::ForIn(names, tok, self.expr(iterable), self.stmts(block))
+            }
+            Stmt::Return(tok, val) => Stmt::Return(tok, val.map(|val| self.expr(val))),
+            Stmt::Throw(tok, val) => Stmt::Throw(tok, self.expr(val)),
+            Stmt::Try(body, catch, finally) => Stmt::Try(
+                self.stmts(body),
+                catch.map(|(name, handler)| (name, self.stmts(handler))),
+                finally.map(|finally| self.stmts(finally)),
+            ),
+            Stmt::Function(decl) => Stmt::Function(self.function(decl)),
+            Stmt::Pub(stmt) => Stmt::Pub(Rc::new(self.stmt(unwrap(stmt))?)),
+            node @ (Stmt::Break(_) | Stmt::Continue(_) | Stmt::UseStmt(..)) => node,
+        })
+    }
+
+    /// Drop the branches whose conditions are constant and false, and every
+    /// branch after one whose condition is constant and true, which becomes the `else`.
+    fn branches(
+        &mut self,
+        cond: Expr,
+        true_br: Vec<Stmt>,
+        elif_brs: Vec<(Expr, Vec<Stmt>)>,
+        else_br: Option<Vec<Stmt>>,
+    ) -> Branches {
+        let mut out = Vec::new();
+        let mut branches = iter::once((cond, true_br)).chain(elif_brs);
+
+        while let Some((cond, block)) = branches.next() {
+            let cond = self.expr(cond);
+
+            match constant(&cond) {
+                Some(false) => self.removed += 1,
+                Some(true) => {
+                    self.removed += branches.count() + else_br.iter().count();
+                    return (out, Some(self.stmts(block)));
+                }
+                None => out.push((cond, self.stmts(block))),
+            }
+        }
+
+        (out, else_br.map(|block| self.stmts(block)))
+    }
+
+    fn function(&mut self, decl: Rc<FnDecl>) -> Rc<FnDecl> {
+        let FnDecl { name, args, optional_args, body } = unwrap(decl);
+        let optional_args = optional_args
+            .into_iter()
+            .map(|(tok, expr)| (tok, self.expr(expr)))
+            .collect();
+
+        Rc::new(FnDecl { name, args, optional_args, body: self.stmts(body) })
+    }
+
+    // exprs
+    fn expr(&mut self, node: Expr) -> Expr {
+        match node {
+            Expr::Binary(left, tok, right) => {
+                let left = self.rc(left);
+                let right = self.rc(right);
+
+                if let (Expr::Literal(l), Expr::Literal(r)) = (left.as_ref(), right.as_ref()) {
+                    if let Some(val) = binary(l, &tok, r) {
+                        self.folded += 1;
+                        return Expr::Literal(val);
+                    }
+                }
+
+                Expr::Binary(left, tok, right)
+            }
+            Expr::Grouping(expr) => match self.expr(unwrap(expr)) {
+                Expr::Literal(val) => Expr::Literal(val),
+                expr => Expr::Grouping(Rc::new(expr)),
+            },
+            Expr::Unary(tok, right) => {
+                let right = self.rc(right);
+
+                if let Expr::Literal(val) = right.as_ref() {
+                    let folded = match tok.ttype {
+                        // the same as the interpreter's `!`
+                        TType::Not => Some(Type::Bool(truthy(val))),
+                        TType::Minus => val.neg().ok(),
+                        _ => None,
+                    };
+
+                    if let Some(val) = folded {
+                        self.folded += 1;
+                        return Expr::Literal(val);
+                    }
+                }
+
+                Expr::Unary(tok, right)
+            }
+            Expr::Logical(left, tok, right) => {
+                let left = self.expr(unwrap(left));
+
+                match constant(&left) {
+                    Some(truthy) => {
+                        self.folded += 1;
+
+                        // the left side is the value if it decides the result
+                        if truthy == (tok.ttype == TType::Or) {
+                            left
+                        } else {
+                            self.expr(unwrap(right))
+                        }
+                    }
+                    None => Expr::Logical(Rc::new(left), tok, self.rc(right)),
+                }
+            }
+            Expr::Ternary(condition, true_br, else_br) => {
+                let condition = self.expr(unwrap(condition));
+
+                match constant(&condition) {
+                    Some(truthy) => {
+                        self.folded += 1;
+                        self.expr(unwrap(if truthy { true_br } else { else_br }))
+                    }
+                    None => Expr::Ternary(Rc::new(condition), self.rc(true_br), self.rc(else_br)),
+                }
+            }
+            Expr::IfExpr(cond, true_br, elif_brs, else_br) => {
+                match self.branches(unwrap(cond), true_br, elif_brs, else_br) {
+                    (branches, None) if branches.is_empty() => Expr::Literal(Type::Nil),
+                    (branches, Some(block)) if branches.is_empty() => Expr::Block(block),
+                    (mut branches, else_br) => {
+                        let (cond, true_br) = branches.remove(0);
+                        Expr::IfExpr(Rc::new(cond), true_br, branches, else_br)
+                    }
+                }
+            }
+            Expr::Assign(id, tok, val) => Expr::Assign(id, tok, self.rc(val)),
+            Expr::Destructure(pattern, ids, val) => Expr::Destructure(pattern, ids, self.rc(val)),
+            Expr::Block(stmts) => Expr::Block(self.stmts(stmts)),
+            Expr::Call(func, tok, args, optional_args) => Expr::Call(
+                self.rc(func),
+                tok,
+                args.into_iter().map(|arg| self.expr(arg)).collect(),
+                optional_args
+                    .into_iter()
+                    .map(|(name, arg)| (name, self.expr(arg)))
+                    .collect(),
+            ),
+            Expr::Get(val, tok, key) => Expr::Get(self.rc(val), tok, self.rc(key)),
+            Expr::Set(var, tok, i, val) => Expr::Set(self.rc(var), tok, self.rc(i), self.rc(val)),
+            Expr::Prop(var, prop) => Expr::Prop(self.rc(var), prop),
+            Expr::Array(items) => {
+                Expr::Array(items.into_iter().map(|item| self.expr(item)).collect())
+            }
+            Expr::Template(parts) => {
+                Expr::Template(parts.into_iter().map(|part| self.expr(part)).collect())
+            }
+            Expr::Map(items) => Expr::Map(
+                items
+                    .into_iter()
+                    .map(|(key, value)| (self.expr(key), self.expr(value)))
+                    .collect(),
+            ),
+            Expr::Range(left, tok, right, inclusive) => {
+                Expr::Range(self.rc(left), tok, self.rc(right), inclusive)
+            }
+            Expr::Lambda(decl) => Expr::Lambda(self.function(decl)),
+            Expr::Match(tok, value, arms) => Expr::Match(
+                tok,
+                self.rc(value),
+                arms.into_iter()
+                    .map(|(pattern, guard, body)| {
+                        (
+                            pattern,
+                            guard.map(|guard| self.expr(guard)),
+                            self.expr(body),
+                        )
+                    })
+                    .collect(),
+            ),
+            node @ (Expr::Literal(_) | Expr::Variable(..)) => node,
+        }
+    }
+
+    fn rc(&mut self, node: Rc<Expr>) -> Rc<Expr> {
+        Rc::new(self.expr(unwrap(node)))
+    }
+}
+
+fn unwrap<T: Clone>(node: Rc<T>) -> T {
+    Rc::try_unwrap(node).unwrap_or_else(|node| (*node).clone())
+}
+
+/// Whether a condition is always true or always false.
+fn constant(expr: &Expr) -> Option<bool> {
+    match expr {
+        Expr::Literal(val) => Some(truthy(val)),
+        _ => None,
+    }
+}
+
+fn truthy(val: &Type) -> bool {
+    match val {
+        Type::Nil => false,
+        Type::Bool(v) => *v,
+        _ => true,
+    }
+}
+
+/// The value of a binary operation, unless it is an error that should be raised when run.
+fn binary(left: &Type, tok: &Token, right: &Type) -> Option<Type> {
+    let val: TResult = match tok.ttype {
+        TType::Plus => left.add(right),
+        TType::Minus => left.sub(right),
+        TType::Times => left.mult(right),
+        TType::Divide => left.div(right),
+        TType::Mod => left.modulo(right),
+        TType::Pow => left.pow(right),
+
+        TType::EqEq => Ok(Type::Bool(left == right)),
+        TType::NotEq => Ok(Type::Bool(left != right)),
+
+        TType::Less => Ok(Type::Bool(left < right)),
+        TType::Greater => Ok(Type::Bool(left > right)),
+        TType::LessEq => Ok(Type::Bool(left <= right)),
+        TType::GreaterEq => Ok(Type::Bool(left >= right)),
+        _ => return None,
+    };
+
+    val.ok()
+}
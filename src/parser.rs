@@ -1,14 +1,26 @@
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::error::{Error, ErrorNote, ErrorType};
 use crate::nodes::expr::Expr;
-use crate::nodes::stmt::{ImportType, Stmt};
-use crate::token::{TType, Token};
+use crate::nodes::pattern::Pattern;
+use crate::nodes::stmt::{FnDecl, ImportType, Stmt};
+use crate::nodes::NodeId;
+use crate::token::{TType, TemplatePart, Token};
 use crate::types::Type;
 
 type PResult = Result<Expr, Error>;
 type SResult = Result<Stmt, Error>;
+type Params = (Vec<Token>, Vec<(Token, Expr)>);
+
+// shared by every parser, so code from different files or REPL inputs never
+// gets the same ids
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn node_id() -> NodeId {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 pub struct Parser {
     tokens: Vec<Token>,
@@ -24,7 +36,11 @@ impl Parser {
         let mut stmts: Vec<Stmt> = Vec::new();
 
         while self.is_valid() {
-            stmts.push(self.stmt()?);
+            if self.get(&[TType::Pub]) {
+                stmts.push(self.pub_stmt()?);
+            } else {
+                stmts.push(self.stmt()?);
+            }
         }
 
         Ok(stmts)
@@ -33,6 +49,13 @@ impl Parser {
     // recursive descent
     // statements
     fn stmt(&mut self) -> SResult {
+        if self.check(TType::Pub) {
+            return Err(Error::new(
+                self.peek().lineinfo,
+                "Only top-level definitions can be marked 'pub'.".into(),
+                ErrorType::SyntaxError,
+            ));
+        }
         if self.get(&[TType::If]) {
             let (cond, if_br, elif_brs, else_br) = self.if_stmt()?;
             return Ok(Stmt::IfStmt(cond, if_br, elif_brs, else_br));
@@ -40,19 +63,33 @@ impl Parser {
         if self.get(&[TType::Var]) {
             return self.var_decl();
         }
+        if self.get(&[TType::Match]) {
+            // like if statements, a match statement needs no ';'
+            let expr = self.match_expr()?;
+            self.get(&[TType::Semi]);
+            return Ok(Stmt::ExprStmt(expr));
+        }
         if self.get(&[TType::While]) {
             return self.while_stmt();
         }
         if self.get(&[TType::Do]) {
             return self.dowhile_stmt();
         }
+        if self.get(&[TType::For]) {
+            return self.for_stmt();
+        }
         if self.get(&[TType::LeftBrace]) {
             return Ok(Stmt::Block(self.block()?));
         }
-        if self.get(&[TType::Break, TType::Continue, TType::Return]) {
+        if self.get(&[TType::Try]) {
+            return self.try_stmt();
+        }
+        if self.get(&[TType::Break, TType::Continue, TType::Return, TType::Throw]) {
             return self.controlflow_stmt();
         }
-        if self.get(&[TType::Fn]) {
+        // `fn (...) {}` is an anonymous function expression
+        if self.check(TType::Fn) && self.peek_n(1).ttype != TType::LeftParen {
+            self.next();
             return self.fn_stmt();
         }
         if self.get(&[TType::Use]) {
@@ -66,8 +103,8 @@ impl Parser {
         let expr = self.expr()?;
 
         if !self.get(&[TType::Semi]) {
-            let semi = self.peek();
-            if semi.ttype != TType::RightBrace && semi.ttype != TType::EOF {
+            let semi = &self.peek().ttype;
+            if *semi != TType::RightBrace && *semi != TType::EOF {
                 return Err(Error::new_n(
                     self.prev().lineinfo,
                     "Expected ';' after statement.".into(),
@@ -122,32 +159,48 @@ impl Parser {
         let mut vars = Vec::new();
 
         loop {
-            if let TType::Identifier(name) = self.next().ttype {
-                let value = if self.get(&[TType::Eq]) {
-                    self.expr()?
-                } else {
-                    Expr::Literal(Type::Nil)
-                };
-
-                vars.push((name, value));
+            let tok = self.peek().clone();
 
-                match self.next().ttype {
-                    TType::Semi => break,
-                    TType::Comma => continue,
-                    _ => {
-                        return Err(Error::new(
-                            self.prev().lineinfo,
-                            "Expected ',' or ';' after variable declaration.".into(),
-                            ErrorType::SyntaxError,
-                        ))
-                    }
+            let pattern = match tok.ttype {
+                TType::Identifier(_) => {
+                    self.next();
+                    Pattern::Binding(tok)
                 }
+                // var [a, ..rest] = arr; or var {{ "x": x }} = point;
+                TType::LeftBrack | TType::LeftBBrace => self.pattern()?,
+                _ => {
+                    return Err(Error::new(
+                        tok.lineinfo,
+                        "Expected variable name".into(),
+                        ErrorType::SyntaxError,
+                    ))
+                }
+            };
+
+            let value = if self.get(&[TType::Eq]) {
+                self.expr()?
+            } else if let Pattern::Binding(_) = pattern {
+                Expr::Literal(Type::Nil)
             } else {
                 return Err(Error::new(
-                    self.prev().lineinfo,
-                    "Expected variable name".into(),
+                    self.peek().lineinfo,
+                    "Expected '=' after destructuring pattern.".into(),
                     ErrorType::SyntaxError,
                 ));
+            };
+
+            vars.push((pattern, value));
+
+            match self.next().ttype {
+                TType::Semi => break,
+                TType::Comma => continue,
+                _ => {
+                    return Err(Error::new(
+                        self.prev().lineinfo,
+                        "Expected ',' or ';' after variable declaration.".into(),
+                        ErrorType::SyntaxError,
+                    ))
+                }
             }
         }
 
@@ -175,14 +228,114 @@ impl Parser {
             "Expected ';' after do while loop condition.".into(),
         )?;
 
-        Ok(Stmt::Block(vec![
-            Stmt::Block(body.clone()),
-            Stmt::WhileStmt(condition, body.clone()),
-        ]))
+        Ok(Stmt::DoWhile(body, condition))
+    }
+
+    fn try_stmt(&mut self) -> SResult {
+        let tok = self.prev().clone();
+        self.consume(TType::LeftBrace, "Expected '{' after try keyword.".into())?;
+        let body = self.block()?;
+
+        let catch = if self.get(&[TType::Catch]) {
+            let name = self.next().clone();
+            if !matches!(name.ttype, TType::Identifier(_)) {
+                return Err(Error::new(
+                    name.lineinfo,
+                    "Expected a variable name after catch keyword.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+
+            self.consume(TType::LeftBrace, "Expected '{' after catch variable.".into())?;
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+
+        let finally = if self.get(&[TType::Finally]) {
+            self.consume(TType::LeftBrace, "Expected '{' after finally keyword.".into())?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(Error::new(
+                tok.lineinfo,
+                "Expected 'catch' or 'finally' after try block.".into(),
+                ErrorType::SyntaxError,
+            ));
+        }
+
+        Ok(Stmt::Try(body, catch, finally))
+    }
+
+    fn for_stmt(&mut self) -> SResult {
+        let paren = if self.get(&[TType::LeftParen]) {
+            Some(self.prev().clone())
+        } else {
+            None
+        };
+
+        let mut names = Vec::new();
+
+        loop {
+            let name = self.next().clone();
+
+            if let TType::Identifier(_) = name.ttype {
+                names.push(name);
+            } else {
+                return Err(Error::new(
+                    name.lineinfo,
+                    "Expected loop variable name after 'for' keyword.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+
+            if !self.get(&[TType::Comma]) {
+                break;
+            }
+        }
+
+        if names.len() > 2 {
+            return Err(Error::new_n(
+                names[2].lineinfo,
+                "Too many loop variables.".into(),
+                ErrorType::SyntaxError,
+                vec![ErrorNote::Note(
+                    "A for loop binds either one value or a key and a value.".into(),
+                )],
+            ));
+        }
+
+        let tok = self.consume(
+            TType::In,
+            "Expected 'in' after for loop variables.".into(),
+        )?;
+        let iterable = self.expr()?;
+
+        if let Some(paren) = paren {
+            self.consume_n(
+                TType::RightParen,
+                "Expected ')' after for loop iterable.".into(),
+                vec![ErrorNote::Expect(
+                    paren.lineinfo,
+                    "Expected the ')' to match this.".into(),
+                )],
+            )?;
+        }
+
+        self.consume(
+            TType::LeftBrace,
+            "Expected '{' after for loop iterable.".into(),
+        )?;
+        let body = self.block()?;
+
+        Ok(Stmt::ForIn(names, tok, iterable, body))
     }
 
     fn controlflow_stmt(&mut self) -> SResult {
-        let tok = self.prev();
+        let tok = self.prev().clone();
         let stype: String;
 
         let out = match tok.ttype {
@@ -206,6 +359,10 @@ impl Parser {
 
                 Stmt::Return(tok, val)
             }
+            TType::Throw => {
+                stype = "throw keyword".into();
+                Stmt::Throw(tok, self.expr()?)
+            }
             _ => panic!(),
         };
 
@@ -214,27 +371,51 @@ impl Parser {
     }
 
     fn fn_stmt(&mut self) -> SResult {
-        let name = self.peek();
+        let name = self.peek().clone();
 
         if let TType::Identifier(_) = name.ttype {
             self.next();
             let (params, optional_params, block) = self.finish_fn("function name".into())?;
 
-            return Ok(Stmt::Function(name, params, optional_params, block));
+            Ok(Stmt::Function(Rc::new(FnDecl {
+                name,
+                args: params,
+                optional_args: optional_params,
+                body: block,
+            })))
         } else {
-            return Err(Error::new(
+            Err(Error::new(
                 name.lineinfo,
                 "Expected function name after 'fn' keyword.".into(),
                 ErrorType::SyntaxError,
-            ));
+            ))
         }
     }
 
+    fn pub_stmt(&mut self) -> SResult {
+        let tok = self.prev().clone();
+
+        let stmt = if self.check(TType::Fn) && self.peek_n(1).ttype != TType::LeftParen {
+            self.next();
+            self.fn_stmt()?
+        } else if self.get(&[TType::Var]) {
+            self.var_decl()?
+        } else {
+            return Err(Error::new(
+                tok.lineinfo,
+                "Expected a function or variable declaration after 'pub'.".into(),
+                ErrorType::SyntaxError,
+            ));
+        };
+
+        Ok(Stmt::Pub(Rc::new(stmt)))
+    }
+
     fn use_stmt(&mut self) -> SResult {
-        let name = self.next();
+        let name = self.next().clone();
 
-        let out = if matches!(name.ttype, TType::Identifier(_)) {
-            // Stmt::UseStmt(name)
+        // use io; or use "./lib/utils.eo";
+        let out = if matches!(name.ttype, TType::Identifier(_) | TType::String(_)) {
             if self.get(&[TType::Dot]) {
                 // use io.*
                 if self.get(&[TType::Times]) {
@@ -245,7 +426,7 @@ impl Parser {
                         let mut fns = Vec::new();
 
                         while self.peek().ttype != TType::RightBrace {
-                            let next = self.next();
+                            let next = self.next().clone();
                             fns.push(match next.ttype {
                                 TType::Identifier(_) => next,
                                 _ => {
@@ -273,7 +454,7 @@ impl Parser {
 
                         // use io.println
                     } else {
-                        let tok = self.next();
+                        let tok = self.next().clone();
                         if let TType::Identifier(_) = tok.ttype {
                             Stmt::UseStmt(name, ImportType::Multiple(vec![tok]))
                         } else {
@@ -288,9 +469,7 @@ impl Parser {
             } else {
                 Stmt::UseStmt(name, ImportType::Mod)
             }
-        }
-        // todo: string syntax???
-        else {
+        } else {
             return Err(Error::new(
                 name.lineinfo,
                 "Expected an identifier or a string after use statement.".into(),
@@ -312,7 +491,7 @@ impl Parser {
         let mut expr = self.ternary()?;
 
         if self.get(&[TType::DotDot, TType::DotEq]) {
-            let tok = self.prev();
+            let tok = self.prev().clone();
             let right = self.expr()?;
 
             let inclusive = match tok.ttype {
@@ -357,7 +536,7 @@ impl Parser {
             TType::PowEq,
             TType::ModEq,
         ]) {
-            let eq = self.prev();
+            let eq = self.prev().clone();
             let val = self.expr()?;
 
             let tok = if eq.ttype == TType::Eq {
@@ -377,12 +556,13 @@ impl Parser {
                 })
             };
 
-            if let Expr::Variable(var) = expr {
+            if let Expr::Variable(id, var) = expr {
                 return Ok(Expr::Assign(
+                    id,
                     var.clone(),
                     if let Some(t) = tok {
                         Rc::new(Expr::Binary(
-                            Rc::new(Expr::Variable(var.clone())),
+                            Rc::new(Expr::Variable(node_id(), var.clone())),
                             t,
                             Rc::new(val),
                         ))
@@ -390,6 +570,12 @@ impl Parser {
                         Rc::new(val)
                     },
                 ));
+            } else if let (Expr::Array(_) | Expr::Map(_), None) = (&expr, &tok) {
+                // [a, b] = [b, a]
+                let pattern = self.expr_pattern(&expr, &eq)?;
+                let ids = pattern.bindings().iter().map(|_| node_id()).collect();
+
+                return Ok(Expr::Destructure(Rc::new(pattern), ids, Rc::new(val)));
             } else if let Expr::Get(ref var, ref brack, ref i) = expr {
                 // var[idx] = val
                 return Ok(Expr::Set(
@@ -414,11 +600,48 @@ impl Parser {
         Ok(expr)
     }
 
+    /// turn the left side of a destructuring assignment into a pattern
+    fn expr_pattern(&self, expr: &Expr, eq: &Token) -> Result<Pattern, Error> {
+        Ok(match expr {
+            Expr::Variable(_, tok) if tok.ttype == TType::Identifier("_".into()) => Pattern::Wildcard,
+            Expr::Variable(_, tok) => Pattern::Binding(tok.clone()),
+            Expr::Array(items) => Pattern::Array(
+                eq.clone(),
+                items
+                    .iter()
+                    .map(|item| self.expr_pattern(item, eq))
+                    .collect::<Result<_, _>>()?,
+                None,
+            ),
+            Expr::Map(items) => Pattern::Map(
+                eq.clone(),
+                items
+                    .iter()
+                    .map(|(key, value)| match key {
+                        Expr::Literal(key) => Ok((key.to_string(), self.expr_pattern(value, eq)?)),
+                        _ => Err(Error::new(
+                            eq.lineinfo,
+                            "Only literal keys can be destructured.".into(),
+                            ErrorType::SyntaxError,
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => {
+                return Err(Error::new(
+                    eq.lineinfo,
+                    "Only variables can be assigned to when destructuring.".into(),
+                    ErrorType::SyntaxError,
+                ))
+            }
+        })
+    }
+
     fn or(&mut self) -> PResult {
         let mut expr = self.and()?;
 
         while self.get(&[TType::Or]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.and()?;
             expr = Expr::Logical(Rc::new(expr), op, Rc::new(right));
         }
@@ -430,7 +653,7 @@ impl Parser {
         let mut expr = self.equality()?;
 
         while self.get(&[TType::And]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.equality()?;
             expr = Expr::Logical(Rc::new(expr), op, Rc::new(right));
         }
@@ -442,7 +665,7 @@ impl Parser {
         let mut expr = self.comp()?;
 
         while self.get(&[TType::NotEq, TType::EqEq]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.comp()?;
             expr = Expr::Binary(Rc::new(expr), op, Rc::new(right));
         }
@@ -454,7 +677,7 @@ impl Parser {
         let mut expr = self.add()?;
 
         while self.get(&[TType::Greater, TType::Less, TType::GreaterEq, TType::LessEq]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.add()?;
             expr = Expr::Binary(Rc::new(expr), op, Rc::new(right));
         }
@@ -466,7 +689,7 @@ impl Parser {
         let mut expr = self.mult()?;
 
         while self.get(&[TType::Minus, TType::Plus]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.mult()?;
             expr = Expr::Binary(Rc::new(expr), op, Rc::new(right));
         }
@@ -478,7 +701,7 @@ impl Parser {
         let mut expr = self.unary()?;
 
         while self.get(&[TType::Times, TType::Divide, TType::Mod]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.unary()?;
             expr = Expr::Binary(Rc::new(expr), op, Rc::new(right));
         }
@@ -488,7 +711,7 @@ impl Parser {
 
     fn unary(&mut self) -> PResult {
         if self.get(&[TType::Not, TType::Minus]) {
-            let op = self.prev();
+            let op = self.prev().clone();
             let right = self.unary()?;
             return Ok(Expr::Unary(op, Rc::new(right)));
         }
@@ -503,7 +726,7 @@ impl Parser {
             if self.get(&[TType::LeftParen]) {
                 expr = self.finish_call(&mut expr)?;
             } else if self.get(&[TType::LeftBrack]) {
-                let tok = self.prev();
+                let tok = self.prev().clone();
                 let val = self.expr()?;
                 self.consume_n(
                     TType::RightBrack,
@@ -515,7 +738,7 @@ impl Parser {
                 )?;
                 expr = Expr::Get(Rc::new(expr), tok, Rc::new(val));
             } else if self.get(&[TType::Dot]) {
-                let name = self.next();
+                let name = self.next().clone();
                 if let TType::Identifier(_) = &name.ttype {
                     expr = Expr::Prop(Rc::new(expr), name)
                 } else {
@@ -560,8 +783,27 @@ impl Parser {
             return Ok(Expr::IfExpr(Rc::new(cond), if_br, elif_brs, else_br));
         }
 
+        if self.get(&[TType::Match]) {
+            return self.match_expr();
+        }
+
+        if self.get(&[TType::Fn]) {
+            let tok = self.prev().clone();
+            let (params, optional_params, block) = self.finish_fn("'fn' keyword".into())?;
+            return Ok(Expr::Lambda(Rc::new(FnDecl {
+                name: tok,
+                args: params,
+                optional_args: optional_params,
+                body: block,
+            })));
+        }
+
+        if self.get(&[TType::Pipe]) {
+            return self.lambda();
+        }
+
         if self.get(&[TType::LeftParen]) {
-            let tok = self.prev();
+            let tok = self.prev().clone();
             let expr = self.expr()?;
             self.consume_n(
                 TType::RightParen,
@@ -577,11 +819,15 @@ impl Parser {
         // literal-like
         self.next();
 
-        let tok = self.prev();
+        let tok = self.prev().clone();
         Ok(match &tok.ttype {
             TType::String(x) => Expr::Literal(Type::String(x.clone())),
-            TType::Number(x) => Expr::Literal(Type::Float(*x)),
-            TType::Identifier(_) => Expr::Variable(tok),
+            TType::Int(x) => Expr::Literal(Type::Int(*x)),
+            TType::BigInt(x) => Expr::Literal(Type::BigInt(x.clone())),
+            TType::Decimal(x) => Expr::Literal(Type::Decimal(x.clone())),
+            TType::Float(x) => Expr::Literal(Type::Float(*x)),
+            TType::Template(parts) => self.template(parts)?,
+            TType::Identifier(_) => Expr::Variable(node_id(), tok),
             _ => {
                 return Err(Error::new(
                     tok.lineinfo,
@@ -592,8 +838,44 @@ impl Parser {
         })
     }
 
+    fn template(&mut self, parts: &[TemplatePart]) -> PResult {
+        let mut exprs = Vec::new();
+
+        for part in parts {
+            exprs.push(match part {
+                TemplatePart::Str(str) => Expr::Literal(Type::String(str.clone())),
+                TemplatePart::Expr(tokens) => Parser::new(tokens.clone()).interpolation()?,
+            });
+        }
+
+        Ok(Expr::Template(exprs))
+    }
+
+    /// The expression inside a template string's `${}`, which must be all of it.
+    fn interpolation(&mut self) -> PResult {
+        if !self.is_valid() {
+            return Err(Error::new(
+                self.peek().lineinfo,
+                "Expected an expression inside '${}'.".into(),
+                ErrorType::SyntaxError,
+            ));
+        }
+
+        let expr = self.expr()?;
+
+        if self.is_valid() {
+            return Err(Error::new(
+                self.peek().lineinfo,
+                "Expected '}' after the template expression.".into(),
+                ErrorType::SyntaxError,
+            ));
+        }
+
+        Ok(expr)
+    }
+
     fn block(&mut self) -> Result<Vec<Stmt>, Error> {
-        let tok = self.prev();
+        let tok = self.prev().clone();
         let mut stmts: Vec<Stmt> = Vec::new();
 
         while !self.check(TType::RightBrace) && self.is_valid() {
@@ -663,6 +945,216 @@ impl Parser {
         Ok(Expr::Map(vals))
     }
 
+    fn match_expr(&mut self) -> PResult {
+        let tok = self.prev().clone();
+        let value = self.expr()?;
+        let brace = self.consume(TType::LeftBrace, "Expected '{' after match value.".into())?;
+
+        let mut arms = Vec::new();
+
+        while !self.check(TType::RightBrace) && self.is_valid() {
+            let pattern = self.pattern()?;
+            let guard = if self.get(&[TType::If]) {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+
+            self.consume(TType::FatArrow, "Expected '=>' after match pattern.".into())?;
+            let body = self.expr()?;
+            arms.push((pattern, guard, body));
+
+            // the ',' is optional after a '}'
+            if !self.get(&[TType::Comma])
+                && self.prev().ttype != TType::RightBrace
+                && !self.check(TType::RightBrace)
+            {
+                return Err(Error::new(
+                    self.peek().lineinfo,
+                    "Expected ',' after match arm.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+        }
+
+        self.consume_n(
+            TType::RightBrace,
+            "Expected '}' after match arms.".into(),
+            vec![ErrorNote::Expect(
+                brace.lineinfo,
+                "Expected '}' to match this.".into(),
+            )],
+        )?;
+
+        Ok(Expr::Match(tok, Rc::new(value), arms))
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, Error> {
+        let tok = self.peek().clone();
+
+        match &tok.ttype {
+            TType::Identifier(name) => {
+                self.next();
+                if name == "_" {
+                    Ok(Pattern::Wildcard)
+                } else {
+                    Ok(Pattern::Binding(tok))
+                }
+            }
+            TType::LeftBrack => {
+                self.next();
+                self.array_pattern(tok)
+            }
+            TType::LeftBBrace => {
+                self.next();
+                self.map_pattern(tok)
+            }
+            _ => {
+                let lit = self.pattern_literal()?;
+
+                if self.get(&[TType::DotDot, TType::DotEq]) {
+                    let inclusive = self.prev().ttype == TType::DotEq;
+                    let end = self.pattern_literal()?;
+
+                    if lit.is_number() && end.is_number() {
+                        Ok(Pattern::Range(lit, end, inclusive))
+                    } else {
+                        Err(Error::new(
+                            tok.lineinfo,
+                            "Range patterns can only contain numbers.".into(),
+                            ErrorType::SyntaxError,
+                        ))
+                    }
+                } else {
+                    Ok(Pattern::Literal(lit))
+                }
+            }
+        }
+    }
+
+    fn pattern_literal(&mut self) -> Result<Type, Error> {
+        let tok = self.next().clone();
+
+        Ok(match tok.ttype {
+            TType::Int(x) => Type::Int(x),
+            TType::BigInt(x) => Type::BigInt(x),
+            TType::Decimal(x) => Type::Decimal(x),
+            TType::Float(x) => Type::Float(x),
+            TType::Minus => match self.next().ttype.clone() {
+                TType::Int(x) => Type::Int(-x),
+                TType::BigInt(x) => Type::BigInt(x).neg().unwrap(),
+                TType::Decimal(x) => Type::Decimal(-x),
+                TType::Float(x) => Type::Float(-x),
+                _ => {
+                    return Err(Error::new(
+                        tok.lineinfo,
+                        "Expected a number after '-' in pattern.".into(),
+                        ErrorType::SyntaxError,
+                    ))
+                }
+            },
+            TType::String(x) => Type::String(x),
+            TType::True => Type::Bool(true),
+            TType::False => Type::Bool(false),
+            TType::Nil => Type::Nil,
+            _ => {
+                return Err(Error::new(
+                    tok.lineinfo,
+                    format!("Unexpected token '{}' in pattern.", tok.ttype),
+                    ErrorType::SyntaxError,
+                ))
+            }
+        })
+    }
+
+    fn array_pattern(&mut self, tok: Token) -> Result<Pattern, Error> {
+        let mut items = Vec::new();
+        let mut rest = None;
+
+        while !self.check(TType::RightBrack) && self.is_valid() {
+            if self.get(&[TType::DotDot]) {
+                if rest.is_some() {
+                    return Err(Error::new(
+                        self.prev().lineinfo,
+                        "Only one '..' is allowed in an array pattern.".into(),
+                        ErrorType::SyntaxError,
+                    ));
+                }
+
+                let name = if let TType::Identifier(_) = self.peek().ttype {
+                    Some(self.next().clone())
+                } else {
+                    None
+                };
+                rest = Some((items.len(), name));
+            } else {
+                items.push(self.pattern()?);
+            }
+
+            if !self.get(&[TType::Comma]) && !self.check(TType::RightBrack) {
+                return Err(Error::new(
+                    self.peek().lineinfo,
+                    "Expected ',' after array pattern item.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+        }
+
+        self.consume(TType::RightBrack, "Expected ']' after array pattern.".into())?;
+
+        Ok(Pattern::Array(tok, items, rest))
+    }
+
+    fn map_pattern(&mut self, tok: Token) -> Result<Pattern, Error> {
+        let mut items = Vec::new();
+
+        while !self.check(TType::RightBBrace) && self.is_valid() {
+            let key = self.pattern_literal()?.to_string();
+            self.consume(TType::Colon, "Expected ':' after map pattern key.".into())?;
+            items.push((key, self.pattern()?));
+
+            if !self.get(&[TType::Comma]) && !self.check(TType::RightBBrace) {
+                return Err(Error::new(
+                    self.peek().lineinfo,
+                    "Expected ',' after map pattern value.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+        }
+
+        self.consume(TType::RightBBrace, "Expected '}}' after map pattern.".into())?;
+
+        Ok(Pattern::Map(tok, items))
+    }
+
+    fn lambda(&mut self) -> PResult {
+        let tok = self.prev().clone();
+        let (params, optional_params) = self.params(TType::Pipe)?;
+
+        self.consume_n(
+            TType::Pipe,
+            "Expected '|' after lambda paramaters.".into(),
+            vec![ErrorNote::Expect(
+                tok.lineinfo,
+                "Expected '|' to match this.".into(),
+            )],
+        )?;
+
+        // |x| { ... } or |x| expr, which returns the expression
+        let body = if self.get(&[TType::LeftBrace]) {
+            self.block()?
+        } else {
+            vec![Stmt::Return(tok.clone(), Some(self.expr()?))]
+        };
+
+        Ok(Expr::Lambda(Rc::new(FnDecl {
+            name: tok,
+            args: params,
+            optional_args: optional_params,
+            body,
+        })))
+    }
+
     // util
     fn finish_call(&mut self, expr: &mut Expr) -> PResult {
         let mut args: Vec<Expr> = Vec::new();
@@ -670,17 +1162,18 @@ impl Parser {
 
         if !self.check(TType::RightParen) {
             loop {
-                match self.peek().ttype {
-                    TType::Identifier(name) => {
-                        if self.peek_n(1).ttype == TType::Eq {
-                            self.next(); // consume the name
-                            self.next(); // consume the eq
-                            optional_args.insert(name, self.expr()?);
-                        } else {
-                            args.push(self.expr()?)
-                        }
+                let name = match &self.peek().ttype {
+                    TType::Identifier(name) if self.peek_n(1).ttype == TType::Eq => Some(name.clone()),
+                    _ => None,
+                };
+
+                match name {
+                    Some(name) => {
+                        self.next(); // consume the name
+                        self.next(); // consume the eq
+                        optional_args.insert(name, self.expr()?);
                     }
-                    _ => args.push(self.expr()?),
+                    None => args.push(self.expr()?),
                 }
 
                 if !self.get(&[TType::Comma]) {
@@ -704,12 +1197,31 @@ impl Parser {
             )?
             .lineinfo;
 
+        let (params, optional_params) = self.params(TType::RightParen)?;
+
+        self.consume_n(
+            TType::RightParen,
+            "Expected ')' after function paramaters.".into(),
+            vec![ErrorNote::Expect(
+                lineinfo,
+                "Expected ')' to match this.".into(),
+            )],
+        )?;
+        self.consume(TType::LeftBrace, "Expected '{' after ')'".into())?;
+
+        let body = self.block()?;
+
+        Ok((params, optional_params, body))
+    }
+
+    /// parse paramaters up to (but not including) `end`
+    fn params(&mut self, end: TType) -> Result<Params, Error> {
         let mut params: Vec<Token> = Vec::new();
         let mut optional_params: Vec<(Token, Expr)> = Vec::new();
 
-        if !self.check(TType::RightParen) {
+        if !self.check(end) {
             loop {
-                let tok = self.peek();
+                let tok = self.peek().clone();
 
                 if let TType::Identifier(_) = tok.ttype {
                     self.next();
@@ -732,25 +1244,13 @@ impl Parser {
             }
         }
 
-        self.consume_n(
-            TType::RightParen,
-            "Expected ')' after function paramaters.".into(),
-            vec![ErrorNote::Expect(
-                lineinfo,
-                "Expected ')' to match this.".into(),
-            )],
-        )?;
-        self.consume(TType::LeftBrace, "Expected '{' after ')'".into())?;
-
-        let body = self.block()?;
-
-        Ok((params, optional_params, body))
+        Ok((params, optional_params))
     }
 
     // errors
     fn consume(&mut self, token: TType, error_message: String) -> Result<Token, Error> {
         if self.check(token) {
-            return Ok(self.next());
+            return Ok(self.next().clone());
         }
 
         self.synchronize();
@@ -768,7 +1268,7 @@ impl Parser {
         notes: Vec<ErrorNote>,
     ) -> Result<Token, Error> {
         if self.check(token) {
-            return Ok(self.next());
+            return Ok(self.next().clone());
         }
 
         self.synchronize();
@@ -795,6 +1295,8 @@ impl Parser {
                 | TType::For
                 | TType::If
                 | TType::Return
+                | TType::Throw
+                | TType::Try
                 | TType::While => {
                     return;
                 }
@@ -823,25 +1325,25 @@ impl Parser {
             return false;
         }
 
-        self.peek().ttype == token
+        self.tokens[self.i].ttype == token
     }
 
     /// get the current token
-    fn peek(&self) -> Token {
-        self.tokens[self.i].clone()
+    fn peek(&self) -> &Token {
+        &self.tokens[self.i]
     }
 
-    fn peek_n(&self, i: usize) -> Token {
-        self.tokens[self.i + i].clone()
+    fn peek_n(&self, i: usize) -> &Token {
+        &self.tokens[self.i + i]
     }
 
     /// get the previous token
-    fn prev(&self) -> Token {
-        self.tokens[self.i - 1].clone()
+    fn prev(&self) -> &Token {
+        &self.tokens[self.i - 1]
     }
 
     /// consume the current token
-    fn next(&mut self) -> Token {
+    fn next(&mut self) -> &Token {
         if self.is_valid() {
             self.i += 1;
         }
@@ -851,6 +1353,6 @@ impl Parser {
 
     /// check if the current token is EOF
     fn is_valid(&self) -> bool {
-        self.peek().ttype != TType::EOF
+        self.tokens[self.i].ttype != TType::EOF
     }
 }
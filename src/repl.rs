@@ -1,11 +1,14 @@
+use std::collections::HashSet;
 use std::path::Path;
 use std::time::Instant;
-use std::{env, process};
+use std::{env, mem, process};
 
 use crate::environment::Environment;
-use crate::error::{Error, LineInfo};
+use crate::error::{Error, ErrorType, LineInfo, WarningType};
 use crate::interpreter::Interpreter;
 use crate::lexer::Lexer;
+use crate::loader::Loader;
+use crate::optimiser::Optimiser;
 use crate::parser::Parser;
 use crate::resolver::Resolver;
 use crate::token::{Token, TType};
@@ -18,8 +21,8 @@ use rustyline::{
     KeyCode, KeyEvent, Modifiers, RepeatCount,
 };
 
-pub fn init(mut environ: Environment, verbose: bool) {
-    let mut code; let mut tokens;
+pub fn init(mut environ: Environment, loader: Loader, verbose: bool) {
+    let mut code; let mut tokens; let mut silenced;
 
     let history_file = if cfg!(windows) {
         if let Ok(dir) = env::var("USERPROFILE") {
@@ -51,6 +54,7 @@ pub fn init(mut environ: Environment, verbose: bool) {
     'main_loop: loop {
         tokens = Vec::new();
         code = String::new();
+        silenced = HashSet::new();
 
         let mut line = 1;
 
@@ -89,7 +93,10 @@ pub fn init(mut environ: Environment, verbose: bool) {
             let mut lexer = Lexer::new(&read);
             lexer.set_lineinfo(LineInfo::new(line, 0));
             match lexer.init() {
-                Ok(mut lexed) => tokens.append(&mut lexed),
+                Ok(mut lexed) => {
+                    tokens.append(&mut lexed);
+                    silenced.extend(lexer.silenced);
+                },
                 Err(error) => {
                     error.display(&read);
                     continue 'main_loop
@@ -99,7 +106,7 @@ pub fn init(mut environ: Environment, verbose: bool) {
             line += 1;
         }
 
-        match run_code(&tokens, &mut environ, verbose) {
+        match run_code(&tokens, &code, silenced, &loader, &mut environ, verbose) {
             Err(error) => error.display(&code),
             Ok(eval) => if eval != Type::Nil {
                 println!("{}", eval);
@@ -108,14 +115,17 @@ pub fn init(mut environ: Environment, verbose: bool) {
     }
 }
 
-fn run_code(
-    code: &[Token],
+pub fn run_code(
+    tokens: &[Token],
+    code: &String,
+    silenced: HashSet<i32>,
+    loader: &Loader,
     environ: &mut Environment,
     verbose: bool,
 ) -> Result<Type, Error> {
     // Turn tokens into AST
     let mut time = Instant::now();
-    let tree = Parser::new(code.to_vec()).init()?;
+    let tree = Parser::new(tokens.to_vec()).init()?;
 
     if verbose {
         eprintln!("parser {:?}", time.elapsed());
@@ -123,18 +133,44 @@ fn run_code(
 
     // Create interpreter
     let mut interpreter = Interpreter::new(tree, environ.clone());
+    interpreter.loader = loader.clone();
 
     // Resolve variables
     time = Instant::now();
-    interpreter = Resolver::new(interpreter).init()?;
+    let mut resolver = Resolver::new(&mut interpreter);
+    resolver.silenced = silenced;
+    resolver.init()?;
 
     if verbose {
         eprintln!("resolver {:?}", time.elapsed());
     }
 
+    // an import may well be for the next line
+    for warning in &resolver.warnings {
+        if warning.error_type != ErrorType::Warning(WarningType::UnusedImport) {
+            warning.display(code);
+        }
+    }
+
+    // Simplify AST, after resolving so that code it removes is still checked
+    if loader.optimise {
+        time = Instant::now();
+        let mut optimiser = Optimiser::new();
+        interpreter.nodes = optimiser.init(mem::take(&mut interpreter.nodes));
+
+        if verbose {
+            eprintln!(
+                "optimiser {:?} ({} folded, {} removed)",
+                time.elapsed(),
+                optimiser.folded,
+                optimiser.removed
+            );
+        }
+    }
+
     // Run interpreter
     time = Instant::now();
-    let eval = interpreter.init()?;
+    let eval = interpreter.run()?;
 
     if verbose {
         eprintln!("interpreter {:?}", time.elapsed());
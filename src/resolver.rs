@@ -1,32 +1,115 @@
+use std::collections::HashSet;
+
 use crate::{
-    error::{Error, ErrorType},
+    environment::Slot,
+    error::{did_you_mean, Error, ErrorNote, ErrorType, WarningType},
     interpreter::Interpreter,
+    loader::{Loader, Names},
     nodes::{
         expr::Expr,
-        stmt::{ImportType, Stmt},
+        stmt::{FnDecl, ImportType, Stmt},
+        NodeId,
     },
     token::{TType, Token},
 };
 
-pub struct Resolver {
-    scopes: Vec<Vec<String>>,
-    interpreter: Interpreter,
+/// How a name came to be in scope, for warnings about it.
+#[derive(Clone, Copy, PartialEq)]
+enum Decl {
+    Variable,
+    Parameter,
+    Import,
+}
+
+struct Local {
+    name: String,
+    /// where the name was declared; names without one are never warned about
+    decl: Option<(Token, Decl)>,
+    used: bool,
+    /// the index of the variable in its scope at runtime, or in the globals
+    slot: usize,
+}
+
+/// A reference to a name not declared yet. A later declaration in one of
+/// the scopes open at the reference settles it.
+struct Reference {
+    id: NodeId,
+    name: Token,
+    /// how many scopes were open at the reference
+    scopes: usize,
+    /// how many of those are still open
+    visible: usize,
+    note: Option<ErrorNote>,
+}
+
+pub struct Resolver<'a> {
+    scopes: Vec<Vec<Local>>,
+    interpreter: &'a mut Interpreter,
+    // how many loops and functions enclose the current node
+    loops: usize,
+    functions: usize,
+    unresolved: Vec<Reference>,
+    /// the modules imported with `use m.*`, whose private names are
+    /// reported as such if they are used
+    star_imports: Vec<(String, Names)>,
+    pub warnings: Vec<Error>,
+    /// lines whose warnings are dropped, see `Lexer::silenced`
+    pub silenced: HashSet<i32>,
 }
 
-impl Resolver {
-    pub fn new(interpreter: Interpreter) -> Self {
+impl<'a> Resolver<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
         Self {
             interpreter,
             scopes: vec![],
+            loops: 0,
+            functions: 0,
+            unresolved: vec![],
+            star_imports: vec![],
+            warnings: vec![],
+            silenced: HashSet::new(),
         }
     }
 
-    pub fn init(&mut self) -> Result<Interpreter, Error> {
+    /// Resolve the interpreter's tree, giving it the slot of each variable.
+    pub fn init(&mut self) -> Result<(), Error> {
         let nodes = self.interpreter.nodes.clone();
 
-        self.resolves(&nodes)?;
+        // names already defined, e.g. by earlier REPL input
+        self.begin_scope();
+        for name in self.interpreter.environ.global_names() {
+            self.define(&name);
+        }
+
+        self.resolve_stmts(&nodes)?;
+        self.end_scope();
+
+        // unused names are only found as their scope ends
+        self.warnings.sort_by_key(Error::line);
+
+        if let Some(reference) = self.unresolved.first() {
+            let var = match &reference.name.ttype {
+                TType::Identifier(v) => v,
+                _ => panic!(),
+            };
+
+            if let Some((module, names)) = self
+                .star_imports
+                .iter()
+                .find(|(_, names)| names.private.contains(var))
+            {
+                return Err(names.missing(&reference.name, module));
+            }
+
+            return Err(Error::new_n(
+                reference.name.lineinfo,
+                format!("Undefined variable {}.", var),
+                ErrorType::ReferenceError,
+                reference.note.iter().cloned().collect(),
+            ));
+        }
 
-        Ok(self.interpreter.clone())
+        Ok(())
     }
 
     // resolve
@@ -36,9 +119,12 @@ impl Resolver {
                 self.resolve_expr(expr)?;
             }
             Stmt::VarDecl(vars) => {
-                for (name, val) in vars {
+                for (pattern, val) in vars {
                     self.resolve_expr(val)?;
-                    self.define(name);
+
+                    for tok in pattern.bindings() {
+                        self.define_tok(tok, Decl::Variable);
+                    }
                 }
             }
             Stmt::Block(stmts) => {
@@ -49,106 +135,122 @@ impl Resolver {
             }
             Stmt::WhileStmt(cond, body) => {
                 self.resolve_expr(cond)?;
-                self.resolves(body)?;
+                self.resolve_loop(body)?;
             }
-            Stmt::Return(_, val) => {
-                if let Some(v) = val {
-                    self.resolve_expr(v)?;
-                }
+            Stmt::DoWhile(body, cond) => {
+                self.resolve_loop(body)?;
+                self.resolve_expr(cond)?;
             }
-            Stmt::Function(name, args, optional_args, block) => {
-                let func_name = match &name.ttype {
-                    TType::Identifier(s) => s,
-                    _ => panic!(),
-                };
-
-                self.define(&func_name);
+            Stmt::ForIn(names, _, iterable, body) => {
+                self.resolve_expr(iterable)?;
 
                 self.begin_scope();
-                for param in args {
-                    let name = match &param.ttype {
-                        TType::Identifier(x) => x,
-                        _ => panic!(),
-                    };
+                for name in names {
+                    self.define_tok(name, Decl::Variable);
+                }
 
-                    self.define(&name);
+                self.resolve_loop(body)?;
+                self.end_scope();
+            }
+            Stmt::Return(tok, val) => {
+                if self.functions == 0 {
+                    return Err(Error::new(
+                        tok.lineinfo,
+                        "Return statements can only be inside functions.".into(),
+                        ErrorType::SyntaxError,
+                    ));
                 }
 
-                for (param, expr) in optional_args {
-                    let name = match &param.ttype {
-                        TType::Identifier(x) => x,
-                        _ => panic!(),
-                    };
+                if let Some(v) = val {
+                    self.resolve_expr(v)?;
+                }
+            }
+            Stmt::Throw(_, val) => {
+                self.resolve_expr(val)?;
+            }
+            Stmt::Try(body, catch, finally) => {
+                self.resolves(body)?;
 
-                    self.define(&name);
-                    self.resolve_expr(expr)?;
+                if let Some((name, handler)) = catch {
+                    self.begin_scope();
+                    self.define_tok(name, Decl::Variable);
+                    self.resolves(handler)?;
+                    self.end_scope();
                 }
 
-                self.resolves(block)?;
-                self.end_scope();
+                if let Some(finally) = finally {
+                    self.resolves(finally)?;
+                }
+            }
+            Stmt::Function(decl) => {
+                self.define_tok(&decl.name, Decl::Variable);
+                self.resolve_fn(decl)?;
             }
-            Stmt::Break(_) => {}
-            Stmt::Continue(_) => {}
+            Stmt::Break(tok) if self.loops == 0 => {
+                return Err(Error::new(
+                    tok.lineinfo,
+                    "Break statements can only be inside loops.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+            Stmt::Continue(tok) if self.loops == 0 => {
+                return Err(Error::new(
+                    tok.lineinfo,
+                    "Continue statements can only be inside loops.".into(),
+                    ErrorType::SyntaxError,
+                ));
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Pub(stmt) => self.resolve_stmt(stmt)?,
 
-            // todo
             Stmt::UseStmt(module, import_type) => {
-                let lf = module.lineinfo;
+                let name = Loader::module_name(module);
 
-                let name = match &module.ttype {
-                    TType::Identifier(n) => n,
-                    _ => panic!(),
+                let names = match &module.ttype {
+                    TType::Identifier(n) if self.interpreter.stdlib.mods.contains_key(n) => {
+                        Names::of(&self.interpreter.stdlib.mods[n])
+                    }
+                    _ => match self.interpreter.loader.find(module) {
+                        Some(path) => self.interpreter.loader.names(module, &path)?,
+                        None => {
+                            return Err(self
+                                .interpreter
+                                .loader
+                                .not_found(module, &self.interpreter.stdlib))
+                        }
+                    },
                 };
 
-                let stdlib = self.interpreter.stdlib.mods.clone();
-                let module = stdlib.get(name).clone();
+                match &import_type {
+                    ImportType::Star => {
+                        // the interpreter defines them in order of name
+                        let mut public = names.public.clone();
+                        public.sort();
+                        public.dedup();
 
-                if let Some(module) = module {
-                    let fns = &module.fns;
-
-                    match &import_type {
-                        ImportType::Star => {
-                            let keys = fns.keys();
-                            for name in keys {
-                                self.define(&name);
-                            }
+                        for name in &public {
+                            self.define(name);
                         }
-                        ImportType::Mod => {
-                            self.define(&name);
-                        }
-                        ImportType::Multiple(itms) => {
-                            for fn_name in itms {
-                                let name_string = match &fn_name.ttype {
-                                    TType::Identifier(s) => s,
-                                    _ => panic!(),
-                                };
-
-                                let maybe_func = fns.get(name_string);
-
-                                match maybe_func {
-                                    Some(_) => {
-                                        self.define(&name_string);
-                                    }
-                                    None => {
-                                        return Err(Error::new(
-                                            lf,
-                                            format!(
-                                                "The item '{}' does not exist in the module '{}'.",
-                                                name_string, name
-                                            )
-                                            .into(),
-                                            ErrorType::ReferenceError,
-                                        ))
-                                    }
-                                }
+
+                        self.star_imports.push((name, names));
+                    }
+                    ImportType::Mod => {
+                        self.define_import(module, &name);
+                    }
+                    ImportType::Multiple(itms) => {
+                        for fn_name in itms {
+                            let name_string = match &fn_name.ttype {
+                                TType::Identifier(s) => s,
+                                _ => panic!(),
+                            };
+
+                            if !names.public.contains(name_string) {
+                                return Err(names.missing(fn_name, &name));
                             }
+
+                            self.define_import(fn_name, name_string);
                         }
                     }
-                } else {
-                    return Err(Error::new(
-                        lf,
-                        format!("Module '{}' not found.", name),
-                        ErrorType::ReferenceError,
-                    ));
                 }
             }
         }
@@ -158,8 +260,14 @@ impl Resolver {
 
     fn resolve_expr(&mut self, expr: &Expr) -> Result<(), Error> {
         match expr {
-            Expr::Assign(var, val) => {
-                self.resolve_local(var);
+            Expr::Assign(id, var, val) => {
+                self.resolve_local(*id, var, false);
+                self.resolve_expr(val)?;
+            }
+            Expr::Destructure(pattern, ids, val) => {
+                for (id, tok) in ids.iter().zip(pattern.bindings()) {
+                    self.resolve_local(*id, tok, false);
+                }
                 self.resolve_expr(val)?;
             }
             Expr::Binary(left, _, right) => {
@@ -170,8 +278,8 @@ impl Resolver {
             Expr::Unary(_, expr) => {
                 self.resolve_expr(expr)?;
             }
-            Expr::Variable(var) => {
-                self.resolve_local(var);
+            Expr::Variable(id, var) => {
+                self.resolve_local(*id, var, true);
             }
             Expr::Block(stmts) => {
                 self.resolves(stmts)?;
@@ -204,7 +312,7 @@ impl Resolver {
                 self.resolve_expr(val)?;
                 self.resolve_expr(key)?;
             }
-            Expr::Array(itms) => {
+            Expr::Array(itms) | Expr::Template(itms) => {
                 for itm in itms {
                     self.resolve_expr(itm)?;
                 }
@@ -227,6 +335,26 @@ impl Resolver {
             Expr::Prop(var, _) => {
                 self.resolve_expr(var)?;
             }
+            Expr::Lambda(decl) => {
+                self.resolve_fn(decl)?;
+            }
+            Expr::Match(_, value, arms) => {
+                self.resolve_expr(value)?;
+
+                for (pattern, guard, body) in arms {
+                    self.begin_scope();
+                    for tok in pattern.bindings() {
+                        self.define_tok(tok, Decl::Variable);
+                    }
+
+                    if let Some(guard) = guard {
+                        self.resolve_expr(guard)?;
+                    }
+
+                    self.resolve_expr(body)?;
+                    self.end_scope();
+                }
+            }
         }
 
         Ok(())
@@ -238,39 +366,153 @@ impl Resolver {
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let mut scope = match self.scopes.pop() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let depth = self.scopes.len();
+        let mut settled = vec![];
+
+        self.unresolved.retain(|reference| {
+            let var = match &reference.name.ttype {
+                TType::Identifier(v) => v,
+                _ => panic!(),
+            };
+
+            if reference.visible <= depth {
+                return true;
+            }
+
+            match scope.iter_mut().find(|local| local.name == *var) {
+                Some(local) => {
+                    local.used = true;
+                    settled.push((reference.id, Self::slot(depth, reference.scopes, local.slot)));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        for (id, slot) in settled {
+            self.interpreter.resolve(id, slot);
+        }
+
+        // whatever is left is now only visible from the enclosing scopes
+        for reference in &mut self.unresolved {
+            reference.visible = reference.visible.min(depth);
+        }
+
+        for local in scope {
+            let (tok, decl) = match local.decl {
+                Some(decl) if !local.used && !local.name.starts_with('_') => decl,
+                _ => continue,
+            };
+
+            // globals may be used by whoever imports the module
+            let (what, kind) = match decl {
+                Decl::Import => ("import", WarningType::UnusedImport),
+                _ if depth == 0 => continue,
+                Decl::Variable => ("variable", WarningType::UnusedVariable),
+                Decl::Parameter => ("parameter", WarningType::UnusedVariable),
+            };
+            let notes = match decl {
+                Decl::Import => vec![],
+                _ => vec![ErrorNote::Note(format!(
+                    "Name it '_{}' if this is intentional.",
+                    local.name
+                ))],
+            };
+
+            self.warn(
+                &tok,
+                format!("The {} '{}' is never used.", what, local.name),
+                kind,
+                notes,
+            );
+        }
     }
 
     // resolve
-    fn resolves(&mut self, stmts: &Vec<Stmt>) -> Result<(), Error> {
+    fn resolves(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
         self.begin_scope();
-        for stmt in stmts {
+        self.resolve_stmts(stmts)?;
+        self.end_scope();
+
+        Ok(())
+    }
+
+    fn resolve_stmts(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        let mut warned = false;
+
+        for (i, stmt) in stmts.iter().enumerate() {
             self.resolve_stmt(stmt)?;
+
+            let exit = match stmt {
+                Stmt::Return(tok, _) => Some((tok, "return")),
+                Stmt::Throw(tok, _) => Some((tok, "throw")),
+                Stmt::Break(tok) => Some((tok, "break")),
+                Stmt::Continue(tok) => Some((tok, "continue")),
+                _ => None,
+            };
+
+            if let Some((tok, keyword)) = exit {
+                if !warned && i + 1 < stmts.len() {
+                    warned = true;
+                    self.warn(
+                        tok,
+                        format!("Code after this '{}' is never run.", keyword),
+                        WarningType::Unreachable,
+                        vec![],
+                    );
+                }
+            }
         }
-        self.end_scope();
 
         Ok(())
     }
-    // resolve_local resolves a variable
-    fn resolve_local(&mut self, name: &Token) {
+
+    // resolve_local resolves a variable, `read` unless it is being assigned to
+    fn resolve_local(&mut self, id: NodeId, name: &Token, read: bool) {
         let var = match &name.ttype {
             TType::Identifier(v) => v,
             _ => panic!(),
         };
-        
-        for i in (0..self.scopes.len()).rev() {
-            if self.scopes[i].contains(var) {
-                return self
-                    .interpreter
-                    .resolve(name.clone(), self.scopes.len() - 1 - i);
+
+        let open = self.scopes.len();
+
+        for i in (0..open).rev() {
+            if let Some(local) = self.scopes[i].iter_mut().rev().find(|l| l.name == *var) {
+                local.used |= read;
+                let slot = Self::slot(i, open, local.slot);
+
+                return self.interpreter.resolve(id, slot);
             }
         }
+
+        let note = did_you_mean(var, self.scopes.iter().flatten().map(|l| &l.name));
+        self.unresolved.push(Reference {
+            id,
+            name: name.clone(),
+            scopes: open,
+            visible: open,
+            note,
+        });
+    }
+
+    /// Where a variable in `slot` of scope `scope` is, seen from code with
+    /// `open` scopes open. The outermost scope is the globals.
+    fn slot(scope: usize, open: usize, slot: usize) -> Slot {
+        if scope == 0 {
+            Slot::Global(slot)
+        } else {
+            Slot::Local(open - 1 - scope, slot)
+        }
     }
 
     fn resolve_if(
         &mut self,
         cond: &Expr,
-        true_br: &Vec<Stmt>,
+        true_br: &[Stmt],
         elif_brs: &Vec<(Expr, Vec<Stmt>)>,
         else_br: &Option<Vec<Stmt>>,
     ) -> Result<(), Error> {
@@ -291,13 +533,103 @@ impl Resolver {
         Ok(())
     }
 
+    fn resolve_fn(&mut self, decl: &FnDecl) -> Result<(), Error> {
+        let FnDecl { args, optional_args, body: block, .. } = decl;
+
+        // default values are evaluated where the function is defined
+        for (_, expr) in optional_args {
+            self.resolve_expr(expr)?;
+        }
+
+        self.begin_scope();
+        for param in args.iter().chain(optional_args.iter().map(|(param, _)| param)) {
+            self.define_tok(param, Decl::Parameter);
+        }
+
+        // a loop outside the function can't be broken out of from inside it
+        let loops = std::mem::replace(&mut self.loops, 0);
+        self.functions += 1;
+        let out = self.resolves(block);
+        self.functions -= 1;
+        self.loops = loops;
+        out?;
+        self.end_scope();
+
+        Ok(())
+    }
+
+    fn resolve_loop(&mut self, body: &[Stmt]) -> Result<(), Error> {
+        self.loops += 1;
+        let out = self.resolves(body);
+        self.loops -= 1;
+
+        out
+    }
+
+    fn warn(&mut self, tok: &Token, message: String, kind: WarningType, notes: Vec<ErrorNote>) {
+        if !self.silenced.contains(&tok.lineinfo.line) {
+            self.warnings.push(Error::new_n(
+                tok.lineinfo,
+                message,
+                ErrorType::Warning(kind),
+                notes,
+            ));
+        }
+    }
+
     // define
-    fn define(&mut self, name: &String) {
-        if self.scopes.is_empty() {
-            return;
+    fn define_tok(&mut self, tok: &Token, decl: Decl) {
+        let name = match &tok.ttype {
+            TType::Identifier(name) => name,
+            _ => panic!(),
+        };
+
+        let earlier = self
+            .scopes
+            .iter()
+            .flatten()
+            .rev()
+            .find(|local| local.name == *name)
+            .and_then(|local| local.decl.as_ref());
+
+        if let Some((earlier, _)) = earlier {
+            if !name.starts_with('_') {
+                let note = ErrorNote::Expect(earlier.lineinfo, "Declared here.".into());
+
+                self.warn(
+                    tok,
+                    format!("'{}' shadows an earlier declaration.", name),
+                    WarningType::Shadowing,
+                    vec![note],
+                );
+            }
         }
 
-        let len = self.scopes.len();
-        self.scopes[len - 1].push(name.clone());
+        self.declare(name, Some((tok.clone(), decl)));
+    }
+
+    fn define_import(&mut self, tok: &Token, name: &str) {
+        self.declare(name, Some((tok.clone(), Decl::Import)));
+    }
+
+    fn define(&mut self, name: &String) {
+        self.declare(name, None);
+    }
+
+    fn declare(&mut self, name: &str, decl: Option<(Token, Decl)>) {
+        let slot = match self.scopes.len() {
+            0 => return,
+            1 => self.interpreter.environ.global_slot(name),
+            len => self.scopes[len - 1].len(),
+        };
+
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Local {
+                name: name.to_string(),
+                decl,
+                used: false,
+                slot,
+            });
+        }
     }
 }
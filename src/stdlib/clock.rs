@@ -14,20 +14,21 @@ use crate::{
 };
 
 pub fn new() -> Module {
-    Module {
-        name: "clock".into(),
-        fns: hashmap! {
+    Module::new(
+        "clock".into(),
+        hashmap! {
             "now".into() => native_func!(|_, _, _| {
                 let start = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error getting time.");
-                Ok(Type::Float(start.as_millis() as f32))
+                Ok(Type::Int(start.as_millis() as i64))
             }, 0),
             "fmt".into() => native_func!(|_, args, _| {
 
-                let millis: f32 = match args[0] {
-                    Type::Float(value) => value,
+                let millis: i64 = match args[0] {
+                    Type::Int(value) => value,
+                    Type::Float(value) => value as i64,
                     _ => {
                         eprintln!("Invalid argument passed to clock.fmt!");
-                        0.0
+                        0
                     }
                 };
                 let datetime: DateTime<Utc> = (UNIX_EPOCH + Duration::from_millis(millis as u64)).into();
@@ -35,5 +36,5 @@ pub fn new() -> Module {
                 Ok(Type::String(datetime.format(args[1].to_string().as_str()).to_string()))
             }, 2)
         },
-    }
+    )
 }
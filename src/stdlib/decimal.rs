@@ -0,0 +1,92 @@
+use std::{rc::Rc, str::FromStr};
+
+use bigdecimal::{BigDecimal, RoundingMode};
+use maplit::hashmap;
+
+use super::error;
+use crate::{
+    error::{Error, ErrorType},
+    functions::{Func, FuncType},
+    native_func,
+    types::module::Module,
+    types::Type,
+};
+
+/// Convert a number or numeric string to an exact decimal. Floats go through
+/// their shortest printed form, so `decimal.of(0.1)` is `0.1d`.
+fn of(value: &Type) -> Result<BigDecimal, Error> {
+    if let Some(dec) = value.as_decimal() {
+        return Ok(dec);
+    }
+
+    let text = match value {
+        Type::Float(v) if v.is_finite() => v.to_string(),
+        Type::String(v) => v.trim().to_string(),
+        _ => {
+            return Err(error(
+                format!("Cannot convert {} to a decimal.", value),
+                ErrorType::TypeError,
+            ))
+        }
+    };
+
+    BigDecimal::from_str(&text).map_err(|_| {
+        error(
+            format!("Cannot convert {} to a decimal.", value),
+            ErrorType::TypeError,
+        )
+    })
+}
+
+fn rounding_mode(mode: &Type) -> Result<RoundingMode, Error> {
+    Ok(match mode.to_string().as_str() {
+        "up" => RoundingMode::Up,
+        "down" => RoundingMode::Down,
+        "ceiling" => RoundingMode::Ceiling,
+        "floor" => RoundingMode::Floor,
+        "half_up" => RoundingMode::HalfUp,
+        "half_down" => RoundingMode::HalfDown,
+        "half_even" => RoundingMode::HalfEven,
+        _ => {
+            return Err(error(
+                format!(
+                    "Unknown rounding mode {}; expected one of up, down, ceiling, floor, half_up, half_down or half_even.",
+                    mode
+                ),
+                ErrorType::TypeError,
+            ))
+        }
+    })
+}
+
+pub fn new() -> Module {
+    Module::new(
+        "decimal".into(),
+        hashmap! {
+            "of".into() => native_func!(|_, args, _| {
+                Ok(Type::Decimal(of(&args[0])?))
+            }, 1),
+            // round(value, places, mode = "half_even")
+            "round".into() => native_func!(|_, args, opt_args| {
+                let places = match args[1] {
+                    Type::Int(places) => places,
+                    _ => {
+                        return Err(error(
+                            "The number of decimal places must be an integer.".into(),
+                            ErrorType::TypeError,
+                        ))
+                    }
+                };
+                let mode = match opt_args.get("mode") {
+                    Some(mode) => rounding_mode(mode)?,
+                    None => RoundingMode::HalfEven,
+                };
+
+                Ok(Type::Decimal(of(&args[0])?.with_scale_round(places, mode)))
+            }, 2),
+            "scale".into() => native_func!(|_, args, _| {
+                Ok(Type::Int(of(&args[0])?.fractional_digit_count()))
+            }, 1),
+        },
+    )
+}
@@ -7,21 +7,30 @@ use maplit::hashmap;
 
 use crate::{
     functions::{Func, FuncType},
+    interpreter::Interpreter,
     native_func,
     types::module::Module,
     types::Type,
 };
 
+/// Print `text` to standard output, or to the loader's output if it has one.
+fn write(interpreter: &Interpreter, text: &str) {
+    match &interpreter.loader.output {
+        Some(output) => output.borrow_mut().push_str(text),
+        None => print!("{}", text),
+    }
+}
+
 pub fn new() -> Module {
-    Module {
-        name: "io".into(),
-        fns: hashmap! {
-            "println".into() => native_func!(|_, args, _| {
-                println!("{}", args[0].to_string());
+    Module::new(
+        "io".into(),
+        hashmap! {
+            "println".into() => native_func!(|interpreter, args, _| {
+                write(interpreter, &format!("{}\n", args[0].to_string()));
                 Ok(Type::Nil)
             }, 1),
-            "print".into() => native_func!(|_, args, _| {
-                print!("{}", args[0].to_string());
+            "print".into() => native_func!(|interpreter, args, _| {
+                write(interpreter, &args[0].to_string());
                 Ok(Type::Nil)
             }, 1),
             "flush".into() => native_func!(|_, _, _| {
@@ -40,7 +49,7 @@ pub fn new() -> Module {
             }, 1),
             "exit".into() => native_func!(|_, args, _| {
                 std::process::exit(match args[0] {
-                    Type::Float(value) => value as i32,
+                    Type::Int(value) => value as i32,
                     _ => {
                         eprintln!("Expected a number for the exit code.");
                         0
@@ -48,5 +57,5 @@ pub fn new() -> Module {
                 });
             }, 1),
         },
-    }
+    )
 }
@@ -10,9 +10,9 @@ use crate::{
 };
 
 pub fn new() -> Module {
-    Module {
-        name: "math".into(),
-        fns: hashmap! {
+    Module::new(
+        "math".into(),
+        hashmap! {
             // nums
             "infinity".into() => Type::Float(f32::INFINITY),
             "nan".into() => Type::Float(f32::NAN),
@@ -22,5 +22,5 @@ pub fn new() -> Module {
                 Ok(Type::Float(3f32)) // ???????
             }, 1)
         },
-    }
+    )
 }
@@ -7,11 +7,15 @@ use std::collections::HashMap;
 
 use maplit::hashmap;
 
-use crate::types::module::Module;
+use crate::{
+    error::{Error, ErrorType, LineInfo},
+    types::module::Module,
+};
 
 mod io;
 mod math;
 mod clock;
+mod decimal;
 
 /**
 Easier coding.
@@ -38,6 +42,11 @@ macro_rules! native_func {
     };
 }
 
+/// An error raised by a native function; the interpreter reports it at the call.
+pub fn error(error: String, error_type: ErrorType) -> Error {
+    Error::new(LineInfo::new(0, 0), error, error_type)
+}
+
 #[derive(Clone)]
 pub struct Stdlib {
     pub mods: HashMap<String, Module>,
@@ -50,6 +59,7 @@ impl Stdlib {
                 "io".into() => io::new(),
                 "math".into() => math::new(),
                 "clock".into() => clock::new(),
+                "decimal".into() => decimal::new(),
             },
         }
     }
@@ -1,27 +1,135 @@
 #[cfg(test)]
 mod programs {
-    use crate::{environment::Environment, error::Error, run_string};
-    use std::{fs, process};
+    use crate::{
+        environment::Environment,
+        loader::Loader,
+        run_string, with_stack,
+    };
+    use std::{cell::RefCell, fs, path::Path, process, rc::Rc};
 
-    fn run_file(file: String) -> Result<(), Error> {
-        let code = fs::read_to_string(file).unwrap_or_else(|err| {
-            eprintln!("Error reading file: {}", err);
-            process::exit(1)
-        });
+    /// What is checked of an error, which can't itself leave the thread the file ran on.
+    #[derive(Debug)]
+    struct Failure {
+        /// the error, its type and its message
+        text: String,
+        warning: bool,
+    }
+
+    /// Run a file, returning what it printed.
+    fn run_file(file: String, deny_warnings: bool, vm: bool, optimise: bool) -> Result<String, Failure> {
+        // with the same stack as the command line, for the recursion limit
+        with_stack(move || {
+            let code = fs::read_to_string(&file).unwrap_or_else(|err| {
+                eprintln!("Error reading file: {}", err);
+                process::exit(1)
+            });
+
+            let mut loader = Loader::new(Some(Path::new(&file)), vec!["test/modules".into()]);
+            loader.vm = vm;
+            loader.optimise = optimise;
+            let output = Rc::new(RefCell::new(String::new()));
+            loader.output = Some(output.clone());
+            run_string(&code, &loader, &mut Environment::new(), false, deny_warnings).map_err(
+                |error| Failure {
+                    text: format!("{:?} {:?} {}", error, error.error_type, error.error),
+                    warning: error.is_warning(),
+                },
+            )?;
+
+            let output = output.borrow().clone();
+            Ok(output)
+        })
+    }
+
+    /// the (vm, optimise) settings every program and error is run with
+    const MODES: [(bool, bool); 3] = [(false, false), (true, false), (false, true)];
+
+    /// Usage: `/* expect error: */` (or `warning`) followed by snippets that must all appear in the error
+    fn expected_error(file: &str, kind: &str) -> Vec<String> {
+        let code = fs::read_to_string(file).unwrap();
+        let code = code.split_once(&format!("/* expect {}:", kind)).unwrap().1;
+        let code = code.split_once("*/").unwrap().0;
 
-        run_string(&code, &mut Environment::new(), false)?;
+        code.split_whitespace().map(|x| x.to_owned()).collect()
+    }
+
+    /// Usage: `/* expect output:` followed by the lines the program prints, then `*/`
+    fn expected_output(file: &str) -> Option<String> {
+        let code = fs::read_to_string(file).unwrap();
+        let code = code.split_once("/* expect output:\n")?.1;
 
-        Ok(())
+        Some(code.split_once("*/")?.0.to_owned())
     }
 
     #[test]
     fn programs() {
         let programs = fs::read_dir("test/programs").unwrap();
 
+        for file in programs {
+            let path = file.unwrap().path();
+
+            // subdirectories hold modules imported by the programs
+            if path.is_dir() {
+                continue;
+            }
+
+            let path = path.display().to_string();
+            println!("---- {} ----", path);
+
+            // the programs should be free of warnings, and print what they expect to
+            // (or at least the same as on the tree-walker) in every mode
+            let mut expected = expected_output(&path);
+            for (vm, optimise) in MODES {
+                let output = run_file(path.clone(), true, vm, optimise).unwrap();
+
+                match &expected {
+                    None => expected = Some(output),
+                    Some(expected) => assert_eq!(
+                        &output, expected,
+                        "unexpected output (vm: {}, optimise: {})",
+                        vm, optimise
+                    ),
+                }
+            }
+        }
+    }
+    #[test]
+    fn errors() {
+        let programs = fs::read_dir("test/errors").unwrap();
+
         for file in programs {
             let path = file.unwrap().path().display().to_string();
+            println!("---- {} ----", path);
+
+            for (vm, optimise) in MODES {
+                let actual_error = run_file(path.clone(), false, vm, optimise).unwrap_err().text;
+
+                for snippet in expected_error(&path, "error") {
+                    assert!(
+                        actual_error.contains(&snippet),
+                        "missing '{}' (vm: {}, optimise: {})",
+                        snippet,
+                        vm,
+                        optimise
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn warnings() {
+        let programs = fs::read_dir("test/warnings").unwrap();
+
+        for file in programs {
+            let path = file.unwrap().path().display().to_string();
+            println!("---- {} ----", path);
+
+            let warning = run_file(path.clone(), true, false, false).unwrap_err();
 
-            run_file(path).unwrap();
+            assert!(warning.warning, "not a warning: {}", warning.text);
+            for snippet in expected_error(&path, "warning") {
+                assert!(warning.text.contains(&snippet), "missing '{}'", snippet);
+            }
         }
     }
 }
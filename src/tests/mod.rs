@@ -1,2 +1,4 @@
 mod files;
 mod lexer;
+mod optimiser;
+mod repl;
@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod optimiser_test {
+    use std::rc::Rc;
+
+    use crate::{
+        lexer::Lexer,
+        nodes::{expr::Expr, stmt::Stmt},
+        optimiser::Optimiser,
+        parser::Parser,
+        types::Type,
+    };
+
+    fn optimise(code: &str) -> (Vec<Stmt>, Optimiser) {
+        let tokens = Lexer::new(&code.to_string()).init().unwrap();
+        let nodes = Parser::new(tokens).init().unwrap();
+
+        let mut optimiser = Optimiser::new();
+        let nodes = optimiser.init(Rc::new(nodes));
+
+        (Rc::try_unwrap(nodes).unwrap(), optimiser)
+    }
+
+    fn value(node: &Stmt) -> &Expr {
+        match node {
+            Stmt::ExprStmt(expr) => expr,
+            Stmt::VarDecl(decls) => &decls[0].1,
+            _ => panic!("not an expression: {:?}", node),
+        }
+    }
+
+    #[test]
+    fn folds_constants() {
+        let (nodes, optimiser) = optimise("var day = 60 * 60 * 24; (1 + 2) * -3; \"a\" + \"b\";");
+
+        assert!(matches!(value(&nodes[0]), Expr::Literal(Type::Int(86400))));
+        assert!(matches!(value(&nodes[1]), Expr::Literal(Type::Int(-9))));
+        assert!(matches!(value(&nodes[2]), Expr::Literal(Type::String(s)) if s == "ab"));
+        assert_eq!(optimiser.folded, 6);
+    }
+
+    #[test]
+    fn keeps_errors_for_runtime() {
+        let (nodes, optimiser) = optimise("1 / (2 - 2);");
+
+        // the subtraction is folded, the division by zero is not
+        match value(&nodes[0]) {
+            Expr::Binary(left, _, right) => {
+                assert!(matches!(left.as_ref(), Expr::Literal(Type::Int(1))));
+                assert!(matches!(right.as_ref(), Expr::Literal(Type::Int(0))));
+            }
+            node => panic!("not a division: {:?}", node),
+        }
+        assert_eq!(optimiser.folded, 1);
+    }
+
+    #[test]
+    fn removes_dead_branches() {
+        let (nodes, optimiser) = optimise(
+            "var x = 1;
+            if false { x = 2; } elif x { x = 3; } else { x = 4; }
+            if 1 > 2 { x = 5; } else { x = 6; }
+            while false { x = 7; }
+            x;",
+        );
+
+        assert_eq!(nodes.len(), 4);
+        assert!(
+            matches!(&nodes[1], Stmt::IfStmt(Expr::Variable(..), _, elifs, Some(_)) if elifs.is_empty())
+        );
+        // only the `else` is left, as a block for its scope and value
+        assert!(matches!(value(&nodes[2]), Expr::Block(_)));
+        assert_eq!(optimiser.removed, 3);
+    }
+
+    #[test]
+    fn does_not_fold_removed_code() {
+        let (_, optimiser) = optimise(
+            "if false { 1 + 2; } else { 3 + 4; }
+            var x = true ? 5 + 6 : 7 + 8;
+            false and 9 + 10;",
+        );
+
+        // `3 + 4`, `5 + 6`, the ternary and the `and`
+        assert_eq!(optimiser.folded, 4);
+        assert_eq!(optimiser.removed, 1);
+    }
+
+    #[test]
+    fn keeps_value_of_removed_last_statement() {
+        let (nodes, optimiser) = optimise("var v = { 1; if false { 2; } };");
+
+        // without the nil, the block would be worth 1
+        match value(&nodes[0]) {
+            Expr::Block(stmts) => {
+                assert_eq!(stmts.len(), 2);
+                assert!(matches!(value(&stmts[1]), Expr::Literal(Type::Nil)));
+            }
+            node => panic!("not a block: {:?}", node),
+        }
+        assert_eq!(optimiser.removed, 1);
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod repl_test {
+    use std::collections::HashSet;
+
+    use crate::{
+        environment::Environment, error::LineInfo, lexer::Lexer, loader::Loader, repl::run_code,
+    };
+
+    /// Run each input like the REPL does, in one environment, returning what it prints.
+    fn session(inputs: &[&str]) -> Vec<String> {
+        let loader = Loader::new(None, vec![]);
+        let mut environ = Environment::new();
+
+        inputs
+            .iter()
+            .map(|input| {
+                let code = format!("{}\n", input);
+                let mut lexer = Lexer::new(&input.to_string());
+                lexer.set_lineinfo(LineInfo::new(1, 0));
+                let tokens = lexer.init().unwrap();
+
+                run_code(&tokens, &code, HashSet::new(), &loader, &mut environ, false)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_position_across_inputs() {
+        // both references to `v` end at the same column of line 1
+        let out = session(&[
+            r#"var v = "global";"#,
+            r#"fn get() { var v = "local"; return v; }"#,
+            r#"var both = [get(),          v];"#,
+            r#"both[0] + " " + both[1]"#,
+        ]);
+
+        assert_eq!(out[3], "local global");
+    }
+
+    #[test]
+    fn closures_from_earlier_inputs() {
+        let out = session(&[
+            "fn counter() { var n = 0; return fn() { n += 1; return n; }; }",
+            "var tick = counter();",
+            "tick(); var n = 10; tick()",
+            "n",
+        ]);
+
+        assert_eq!(out[2], "2");
+        assert_eq!(out[3], "10");
+    }
+}
@@ -1,5 +1,8 @@
 use std::fmt::Display;
 
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+
 use crate::error::LineInfo;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -47,17 +50,24 @@ pub enum TType {
 
     Colon,
     Question,
+    Pipe,
+    FatArrow,
 
     // literals
     Identifier(String),
     String(String),
-    Number(f32),
+    Int(i64),
+    BigInt(BigInt),
+    Decimal(BigDecimal),
+    Template(Vec<TemplatePart>),
+    Float(f32),
     True,
     False,
     Nil,
 
     // keywords
     Fn,
+    Pub,
     Return,
     Var,
     Use,
@@ -72,10 +82,23 @@ pub enum TType {
     If,
     Else,
     Elif,
+    Match,
+    Throw,
+    Try,
+    Catch,
+    Finally,
 
     EOF,
 }
 
+/// A piece of a template string: literal text, or the tokens of an embedded
+/// `${...}` expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplatePart {
+    Str(String),
+    Expr(Vec<Token>),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub ttype: TType,
@@ -27,37 +27,25 @@ impl Array {
         let len = self.arr.len();
 
         match num {
-            Type::Float(i) => {
-                if i.is_infinite() || i.is_nan() || // infinite
-                                    i.round() != i
-                // not whole
-                {
-                    return Err((
-                        format!("Only whole numbers are valid index ranges (got {}).", i).into(),
-                        ErrorType::TypeError,
-                    ));
-                }
-
-                let idx;
-
-                if i < 0f32 {
-                    idx = len as f32 + i;
-                } else {
-                    idx = i;
-                }
+            Type::Int(i) => {
+                let idx = if i < 0 { len as i64 + i } else { i };
 
-                if idx < 0f32 || idx as usize >= len {
+                if idx < 0 || idx as usize >= len {
                     return Err((
-                        format!("Index {} out of array range 0-{}.", i, len - 1).into(),
+                        format!("Index {} out of array range 0-{}.", i, len as i64 - 1),
                         ErrorType::ReferenceError,
                     ));
                 }
 
                 Ok(idx as usize)
             }
+            Type::BigInt(i) => Err((
+                format!("Index {} out of array range 0-{}.", i, len as i64 - 1),
+                ErrorType::ReferenceError,
+            )),
             _ => {
                 return Err((
-                    "Arrays can only be indexed with numbers.".into(),
+                    "Arrays can only be indexed with integers.".into(),
                     ErrorType::TypeError,
                 ))
             }
@@ -1,3 +1,6 @@
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+
 use super::Type;
 use std::cmp::Ordering;
 
@@ -5,7 +8,9 @@ impl PartialEq for Type {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Type::String(a), Type::String(b)) => a == b,
-            (Type::Float(a), Type::Float(b)) => a == b,
+            (a, b) if a.is_number() && b.is_number() => {
+                self.partial_cmp(other) == Some(Ordering::Equal)
+            }
             (Type::Nil, Type::Nil) => true,
             (Type::Bool(a), Type::Bool(b)) => a == b,
             _ => false,
@@ -17,10 +22,34 @@ impl PartialOrd for Type {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
             (Type::String(a), Type::String(b)) => a.len().partial_cmp(&b.len()),
-            (Type::Float(a), Type::Float(b)) => a.partial_cmp(b),
-            _ => None,
+            (Type::Int(a), Type::Int(b)) => a.partial_cmp(b),
+            (Type::BigInt(a), Type::BigInt(b)) => a.partial_cmp(b),
+            (Type::BigInt(a), Type::Int(b)) => a.partial_cmp(&BigInt::from(*b)),
+            (Type::Int(a), Type::BigInt(b)) => BigInt::from(*a).partial_cmp(b),
+            (Type::Int(a), Type::Float(b)) => cmp_int_float(&BigInt::from(*a), *b),
+            (Type::Float(a), Type::Int(b)) => cmp_int_float(&BigInt::from(*b), *a).map(Ordering::reverse),
+            (Type::BigInt(a), Type::Float(b)) => cmp_int_float(a, *b),
+            (Type::Float(a), Type::BigInt(b)) => cmp_int_float(b, *a).map(Ordering::reverse),
+            // like arithmetic, decimals and floats don't mix, so they're unordered
+            (Type::Decimal(_), Type::Float(_)) | (Type::Float(_), Type::Decimal(_)) => None,
+            (Type::Decimal(_), _) | (_, Type::Decimal(_)) => {
+                self.as_decimal()?.partial_cmp(&other.as_decimal()?)
+            }
+            _ => self.as_float()?.partial_cmp(&other.as_float()?),
         }
     }
 }
 
+/// Compare an integer with a float exactly, rather than rounding the integer
+/// to a float, which can't hold every integer above 2^24.
+fn cmp_int_float(int: &BigInt, float: f32) -> Option<Ordering> {
+    if float.is_finite() && float.fract() == 0.0 {
+        // an integral float is exactly some integer
+        int.partial_cmp(&BigInt::from_f32(float)?)
+    } else {
+        // a fractional float is small enough that rounding the integer can't pass it
+        int.to_f64()?.partial_cmp(&(float as f64))
+    }
+}
+
 // todo: hash
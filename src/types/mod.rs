@@ -1,5 +1,8 @@
 use std::{cell::RefCell, rc::Rc};
 
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+
 use crate::functions::FuncType;
 use array::Array;
 use map::Map;
@@ -15,12 +18,18 @@ pub mod module;
 
 #[derive(Debug, Clone)]
 pub enum Type {
+    Int(i64),
+    /// An integer outside the range of `Int`; arithmetic results that fit are
+    /// always turned back into an `Int`.
+    BigInt(BigInt),
+    /// An exact decimal number, written with a `d` suffix (`1.10d`).
+    Decimal(BigDecimal),
     Float(f32),
     String(String),
     Bool(bool),
     Array(Rc<RefCell<Array>>),
     Map(Rc<RefCell<Map>>),
-    Module(Module),
+    Module(Rc<Module>),
     Func(FuncType),
     Nil,
 }
@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use super::Type;
+use crate::{environment::Environment, functions::FuncType};
 
 use std::fmt::{ self, Display };
 
@@ -8,11 +9,38 @@ use std::fmt::{ self, Display };
 pub struct Module {
     pub name: String,
     pub fns: HashMap<String, Type>,
+    /// top-level names of the module that are not `pub`
+    pub private: HashSet<String>,
+    /// the globals of a module run from a file, which hold the current values
+    /// of its items (`fns` only has their values when it finished running)
+    pub environ: Option<Environment>,
 }
 
 impl Module {
-    pub fn new(name: String, fns: HashMap<String, Type>) -> Self {
-        Self { name, fns }
+    pub fn new(name: String, mut fns: HashMap<String, Type>) -> Self {
+        // native functions are named after where they live, for tracebacks
+        for (key, value) in fns.iter_mut() {
+            if let Type::Func(FuncType::Native(func)) = value {
+                func.rename(format!("{}.{}", name, key));
+            }
+        }
+
+        Self {
+            name,
+            fns,
+            private: HashSet::new(),
+            environ: None,
+        }
+    }
+
+    /// The current value of the item `name`, if the module exports it.
+    pub fn get(&self, name: &str) -> Option<Type> {
+        let val = self.fns.get(name)?;
+
+        match &self.environ {
+            Some(environ) => environ.global(name),
+            None => Some(val.clone()),
+        }
     }
 
     pub fn to_string(&self, idt: usize) -> String {
@@ -1,114 +1,340 @@
+use std::convert::TryFrom;
+
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_bigint::BigInt;
+use num_traits::{One, ToPrimitive, Zero};
+
 use super::Type;
 use crate::error::ErrorType;
 
 pub type TResult = Result<Type, (String, ErrorType)>;
 
-impl Type {
-    pub fn add(&self, other: &Type) -> TResult {
-        if let (Self::Float(a), Self::Float(b)) = (self, other) {
-            return Ok(Self::Float(a + b));
+/// Number of decimal places kept when a decimal division doesn't terminate.
+const DIV_SCALE: i64 = 28;
+
+/// Extra places kept in the steps of a decimal power, so that rounding them
+/// doesn't change the last place of the result.
+const POW_GUARD_PLACES: i64 = 10;
+
+/// The most bits the integer part of a power may have, about 300,000 digits.
+const MAX_POW_BITS: u64 = 1 << 20;
+
+/// The operands of a numeric operator after promotion: two integers stay
+/// integers (big ones if either side is big), a decimal and an integer are
+/// done as decimals, and anything else involving a float is done in floating
+/// point. Decimals and floats can't be mixed, since that would silently make
+/// the result inexact.
+enum Operands {
+    Int(i64, i64),
+    Big(BigInt, BigInt),
+    Dec(BigDecimal, BigDecimal),
+    Float(f32, f32),
+}
+
+impl Operands {
+    fn of(a: &Type, b: &Type) -> Result<Option<Self>, (String, ErrorType)> {
+        Ok(match (a, b) {
+            (Type::Int(a), Type::Int(b)) => Some(Self::Int(*a, *b)),
+            (Type::BigInt(a), Type::BigInt(b)) => Some(Self::Big(a.clone(), b.clone())),
+            (Type::BigInt(a), Type::Int(b)) => Some(Self::Big(a.clone(), BigInt::from(*b))),
+            (Type::Int(a), Type::BigInt(b)) => Some(Self::Big(BigInt::from(*a), b.clone())),
+            (Type::Decimal(_), Type::Float(_)) | (Type::Float(_), Type::Decimal(_)) => {
+                return Err((
+                    "Decimals and floats can't be mixed; convert the float with decimal.of first."
+                        .into(),
+                    ErrorType::TypeError,
+                ))
+            }
+            (Type::Decimal(_), _) | (_, Type::Decimal(_)) => {
+                a.as_decimal().zip(b.as_decimal()).map(|(a, b)| Self::Dec(a, b))
+            }
+            _ => a.as_float().zip(b.as_float()).map(|(a, b)| Self::Float(a, b)),
+        })
+    }
+}
+
+/// Wrap an integer result, keeping it a plain `Int` whenever it fits.
+fn big(v: BigInt) -> Type {
+    match v.to_i64() {
+        Some(v) => Type::Int(v),
+        None => Type::BigInt(v),
+    }
+}
+
+fn big_pow(a: BigInt, b: &BigInt) -> TResult {
+    match b.to_u32() {
+        Some(exp) if !pow_too_large(&a, exp) => Ok(big(a.pow(exp))),
+        _ => Err(exponent_too_large(b)),
+    }
+}
+
+/// Whether `base ** exp` would have more than `MAX_POW_BITS` bits.
+fn pow_too_large(base: &BigInt, exp: u32) -> bool {
+    // 0, 1 and -1 stay the same size
+    base.bits() > 1 && (base.bits() - 1).saturating_mul(exp as u64) > MAX_POW_BITS
+}
+
+fn exponent_too_large(exp: &impl std::fmt::Display) -> (String, ErrorType) {
+    (
+        format!("The exponent {} is too large.", exp),
+        ErrorType::MathError,
+    )
+}
+
+/// Divide two decimals. A quotient that doesn't terminate is rounded half-even
+/// to `DIV_SCALE` places; trailing zeros are then dropped, but never below the
+/// scale of the operands (so `10.00d / 4` is `2.50`).
+fn dec_div(a: &BigDecimal, b: &BigDecimal) -> BigDecimal {
+    let scale = a.fractional_digit_count().max(b.fractional_digit_count());
+    let quotient = (a / b)
+        .with_scale_round(DIV_SCALE.max(scale), RoundingMode::HalfEven)
+        .normalized();
+
+    if quotient.fractional_digit_count() < scale {
+        quotient.with_scale(scale)
+    } else {
+        quotient
+    }
+}
+
+fn dec_pow(a: BigDecimal, b: &BigDecimal) -> TResult {
+    let exp = match b.is_integer().then(|| b.to_i64()).flatten() {
+        Some(exp) => exp,
+        None => {
+            return Err((
+                "Decimals can only be raised to integer powers.".into(),
+                ErrorType::TypeError,
+            ))
         }
+    };
+
+    if exp < 0 && a.is_zero() {
+        return Err(("Division by 0.".into(), ErrorType::MathError));
+    }
+
+    // the result's integer part is about as large as the base's (or, for a
+    // negative exponent, its reciprocal's) raised to the power
+    let magnitude = match exp {
+        0.. => a.abs(),
+        _ => dec_div(&BigDecimal::one(), &a.abs()),
+    };
+    let (whole, _) = magnitude.with_scale(0).into_bigint_and_exponent();
+
+    match u32::try_from(exp.unsigned_abs()) {
+        Ok(n) if pow_too_large(&(whole + 1), n) => Err(exponent_too_large(b)),
+        Ok(n) if exp >= 0 => Ok(Type::Decimal(dec_pow_n(&a, n))),
+        Ok(n) => Ok(Type::Decimal(dec_div(&BigDecimal::one(), &dec_pow_n(&a, n)))),
+        Err(_) => Err(exponent_too_large(b)),
+    }
+}
 
-        if let (Self::String(a), Self::Float(b)) = (self, other) {
-            return Ok(Self::String(format!("{}{}", a, b)));
+/// `a ** n` by squaring. Steps are rounded to a few more places than
+/// `DIV_SCALE`, and the result to `DIV_SCALE`, so it is exact whenever it has
+/// no more places than that.
+fn dec_pow_n(a: &BigDecimal, mut n: u32) -> BigDecimal {
+    let round = |v: BigDecimal, scale: i64| {
+        if v.fractional_digit_count() > scale {
+            v.with_scale_round(scale, RoundingMode::HalfEven)
+        } else {
+            v
         }
+    };
+    let step_scale = DIV_SCALE + POW_GUARD_PLACES;
 
-        if let (Self::Float(a), Self::String(b)) = (self, other) {
-            return Ok(Self::String(format!("{}{}", a, b)));
+    let mut out = BigDecimal::one();
+    let mut base = a.clone();
+    while n > 0 {
+        if n & 1 == 1 {
+            out = round(out * &base, step_scale);
         }
 
-        if let (Self::String(a), Self::String(b)) = (self, other) {
-            return Ok(Self::String(format!("{}{}", a, b)));
+        n >>= 1;
+        if n > 0 {
+            base = round(&base * &base, step_scale);
         }
+    }
+
+    round(out, DIV_SCALE)
+}
 
-        Err((
-            "Operator '+' can only be applied to strings and numbers.".into(),
-            ErrorType::TypeError,
-        ))
+fn repeat(s: &str, n: i64) -> Type {
+    Type::String(s.repeat(n.max(0) as usize))
+}
+
+impl Type {
+    pub fn is_number(&self) -> bool {
+        matches!(
+            self,
+            Self::Int(_) | Self::BigInt(_) | Self::Decimal(_) | Self::Float(_)
+        )
     }
 
-    pub fn sub(&self, other: &Type) -> TResult {
-        if let (Self::Float(a), Self::Float(b)) = (self, other) {
-            return Ok(Self::Float(a - b));
+    pub(super) fn as_float(&self) -> Option<f32> {
+        match self {
+            Self::Int(v) => Some(*v as f32),
+            Self::BigInt(v) => Some(v.to_f32().unwrap_or(f32::NAN)),
+            Self::Decimal(v) => Some(v.to_f32().unwrap_or(f32::NAN)),
+            Self::Float(v) => Some(*v),
+            _ => None,
         }
+    }
 
-        Err((
-            "Operator '-' can only be applied to numbers.".into(),
-            ErrorType::TypeError,
-        ))
+    /// The exact value of an integer or decimal.
+    pub fn as_decimal(&self) -> Option<BigDecimal> {
+        match self {
+            Self::Int(v) => Some(BigDecimal::from(*v)),
+            Self::BigInt(v) => Some(BigDecimal::from(v.clone())),
+            Self::Decimal(v) => Some(v.clone()),
+            _ => None,
+        }
     }
 
-    pub fn mult(&self, other: &Type) -> TResult {
-        if let (Self::Float(a), Self::Float(b)) = (self, other) {
-            return Ok(Self::Float(a * b));
+    pub fn add(&self, other: &Type) -> TResult {
+        match Operands::of(self, other)? {
+            Some(Operands::Int(a, b)) => {
+                return Ok(a
+                    .checked_add(b)
+                    .map_or_else(|| big(BigInt::from(a) + b), Self::Int))
+            }
+            Some(Operands::Big(a, b)) => return Ok(big(a + b)),
+            Some(Operands::Dec(a, b)) => return Ok(Self::Decimal(a + b)),
+            Some(Operands::Float(a, b)) => return Ok(Self::Float(a + b)),
+            None => {}
         }
 
-        if let (Self::String(a), Self::Float(b)) = (self, other) {
-            if b.fract() != 0.0 {
-                return Err((
-                    "Operator '*' can only be applied to strings and integer numbers.".into(),
-                    ErrorType::TypeError,
-                ));
+        match (self, other) {
+            (Self::String(a), b) if b.is_number() || matches!(b, Self::String(_)) => {
+                Ok(Self::String(format!("{}{}", a, b.to_string())))
             }
-            return Ok(Self::String(a.repeat((*b) as usize)));
+            (a, Self::String(b)) if a.is_number() => {
+                Ok(Self::String(format!("{}{}", a.to_string(), b)))
+            }
+            _ => Err((
+                "Operator '+' can only be applied to strings and numbers.".into(),
+                ErrorType::TypeError,
+            )),
         }
+    }
 
-        if let (Self::Float(a), Self::String(b)) = (self, other) {
-            if a.fract() != 0.0 {
-                return Err((
-                    "Operator '*' can only be applied to strings and integer numbers.".into(),
-                    ErrorType::TypeError,
-                ));
+    pub fn sub(&self, other: &Type) -> TResult {
+        match Operands::of(self, other)? {
+            Some(Operands::Int(a, b)) => Ok(a
+                .checked_sub(b)
+                .map_or_else(|| big(BigInt::from(a) - b), Self::Int)),
+            Some(Operands::Big(a, b)) => Ok(big(a - b)),
+            Some(Operands::Dec(a, b)) => Ok(Self::Decimal(a - b)),
+            Some(Operands::Float(a, b)) => Ok(Self::Float(a - b)),
+            None => Err((
+                "Operator '-' can only be applied to numbers.".into(),
+                ErrorType::TypeError,
+            )),
+        }
+    }
+
+    pub fn mult(&self, other: &Type) -> TResult {
+        match Operands::of(self, other)? {
+            Some(Operands::Int(a, b)) => {
+                return Ok(a
+                    .checked_mul(b)
+                    .map_or_else(|| big(BigInt::from(a) * b), Self::Int))
             }
-            return Ok(Self::String(b.repeat((*a) as usize)));
+            Some(Operands::Big(a, b)) => return Ok(big(a * b)),
+            Some(Operands::Dec(a, b)) => return Ok(Self::Decimal(a * b)),
+            Some(Operands::Float(a, b)) => return Ok(Self::Float(a * b)),
+            None => {}
         }
 
-        Err((
-            "Operator '*' can only be applied to numbers, or numbers and strings.".into(),
-            ErrorType::TypeError,
-        ))
+        match (self, other) {
+            (Self::String(a), Self::Int(b)) | (Self::Int(b), Self::String(a)) => Ok(repeat(a, *b)),
+            (Self::String(_), n) | (n, Self::String(_)) if n.is_number() => Err((
+                "Operator '*' can only be applied to strings and integers.".into(),
+                ErrorType::TypeError,
+            )),
+            _ => Err((
+                "Operator '*' can only be applied to numbers, or numbers and strings.".into(),
+                ErrorType::TypeError,
+            )),
+        }
     }
 
+    /// Division of two integers truncates towards zero; with a float on either
+    /// side the result is a float.
     pub fn div(&self, other: &Type) -> TResult {
-        if let (Self::Float(a), Self::Float(b)) = (self, other) {
-            if *b == 0f32 {
-                return Err(("Division by 0.".into(), ErrorType::MathError));
+        match Operands::of(self, other)? {
+            Some(Operands::Int(_, 0) | Operands::Float(_, 0.0)) => {
+                Err(("Division by 0.".into(), ErrorType::MathError))
+            }
+            Some(Operands::Big(_, b)) if b.is_zero() => {
+                Err(("Division by 0.".into(), ErrorType::MathError))
             }
-            return Ok(Self::Float(a / b));
+            Some(Operands::Dec(_, b)) if b.is_zero() => {
+                Err(("Division by 0.".into(), ErrorType::MathError))
+            }
+            Some(Operands::Int(a, b)) => Ok(a
+                .checked_div(b)
+                .map_or_else(|| big(BigInt::from(a) / b), Self::Int)),
+            Some(Operands::Big(a, b)) => Ok(big(a / b)),
+            Some(Operands::Dec(a, b)) => Ok(Self::Decimal(dec_div(&a, &b))),
+            Some(Operands::Float(a, b)) => Ok(Self::Float(a / b)),
+            None => Err((
+                "Operator '/' can only be applied to numbers.".into(),
+                ErrorType::TypeError,
+            )),
         }
-
-        Err((
-            "Operator '/' can only be applied to numbers.".into(),
-            ErrorType::TypeError,
-        ))
     }
 
     pub fn modulo(&self, other: &Type) -> TResult {
-        if let (Self::Float(a), Self::Float(b)) = (self, other) {
-            if *b == 0f32 {
-                return Err(("Division by 0.".into(), ErrorType::MathError));
+        match Operands::of(self, other)? {
+            Some(Operands::Int(_, 0) | Operands::Float(_, 0.0)) => {
+                Err(("Division by 0.".into(), ErrorType::MathError))
+            }
+            Some(Operands::Big(_, b)) if b.is_zero() => {
+                Err(("Division by 0.".into(), ErrorType::MathError))
+            }
+            Some(Operands::Dec(_, b)) if b.is_zero() => {
+                Err(("Division by 0.".into(), ErrorType::MathError))
             }
-            return Ok(Self::Float(a % b));
+            Some(Operands::Int(a, b)) => Ok(a.checked_rem(b).map_or(Self::Int(0), Self::Int)),
+            Some(Operands::Big(a, b)) => Ok(big(a % b)),
+            Some(Operands::Dec(a, b)) => Ok(Self::Decimal(a % b)),
+            Some(Operands::Float(a, b)) => Ok(Self::Float(a % b)),
+            None => Err((
+                "Operator '%' can only be applied to numbers.".into(),
+                ErrorType::TypeError,
+            )),
         }
-
-        Err((
-            "Operator '%' can only be applied to numbers.".into(),
-            ErrorType::TypeError,
-        ))
     }
 
+    /// An integer raised to a non-negative integer stays an integer; negative
+    /// exponents give a float.
     pub fn pow(&self, other: &Type) -> TResult {
-        if let (Self::Float(a), Self::Float(b)) = (self, other) {
-            if *b == 0f32 {
-                return Err(("Division by 0.".into(), ErrorType::MathError));
+        match Operands::of(self, other)? {
+            Some(Operands::Int(a, b)) if b < 0 => Ok(Self::Float((a as f32).powf(b as f32))),
+            Some(Operands::Int(a, b)) => match u32::try_from(b).ok().and_then(|e| a.checked_pow(e)) {
+                Some(v) => Ok(Self::Int(v)),
+                None => big_pow(BigInt::from(a), &BigInt::from(b)),
+            },
+            Some(Operands::Big(_, b)) if b < BigInt::zero() => {
+                Ok(Self::Float(self.as_float().unwrap().powf(other.as_float().unwrap())))
             }
-            return Ok(Self::Float(a.powf(*b)));
+            Some(Operands::Big(a, b)) => big_pow(a, &b),
+            Some(Operands::Dec(a, b)) => dec_pow(a, &b),
+            Some(Operands::Float(a, b)) => Ok(Self::Float(a.powf(b))),
+            None => Err((
+                "Operator '**' can only be applied to numbers.".into(),
+                ErrorType::TypeError,
+            )),
         }
+    }
 
-        Err((
-            "Operator '%' can only be applied to numbers.".into(),
-            ErrorType::TypeError,
-        ))
+    pub fn neg(&self) -> TResult {
+        match self {
+            Self::Int(v) => Ok(v.checked_neg().map_or_else(|| big(-BigInt::from(*v)), Self::Int)),
+            Self::BigInt(v) => Ok(big(-v)),
+            Self::Decimal(v) => Ok(Self::Decimal(-v)),
+            Self::Float(v) => Ok(Self::Float(-v)),
+            _ => Err(("Only numbers can be negated.".into(), ErrorType::TypeError)),
+        }
     }
 
     // arrays and maps
@@ -139,4 +365,38 @@ impl Type {
             )),
         }
     }
+
+    // iteration
+    /// Returns the (key, value) pairs of an iterable value: indices and items for
+    /// arrays and strings, keys and values (sorted by key) for maps.
+    pub fn iter(&self) -> Result<Vec<(Type, Type)>, (String, ErrorType)> {
+        match self {
+            Type::Array(v) => Ok(v
+                .borrow()
+                .arr
+                .iter()
+                .enumerate()
+                .map(|(i, itm)| (Type::Int(i as i64), itm.clone()))
+                .collect()),
+            Type::Map(v) => {
+                let map = &v.borrow().map;
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+
+                Ok(keys
+                    .into_iter()
+                    .map(|k| (Type::String(k.clone()), map[k].clone()))
+                    .collect())
+            }
+            Type::String(v) => Ok(v
+                .chars()
+                .enumerate()
+                .map(|(i, c)| (Type::Int(i as i64), Type::String(c.to_string())))
+                .collect()),
+            _ => Err((
+                "Only arrays, maps and strings can be iterated over.".into(),
+                ErrorType::TypeError,
+            )),
+        }
+    }
 }
@@ -25,12 +25,15 @@ impl Type {
                 n.borrow().to_string(1)
             }
             Type::Nil => "nil".into(),
+            Type::Int(n) => n.to_string(),
+            Type::BigInt(n) => n.to_string(),
+            Type::Decimal(n) => n.to_string(),
             Type::Float(n) => n.to_string(),
             Type::String(n) => n.clone(),
             Type::Bool(n) => n.to_string(),
             Type::Func(n) => Call::to_string(n),
             Type::Module(n) => {
-                n.to_string(1)
+                n.as_ref().to_string(1)
             }
         }
     }
@@ -66,6 +69,9 @@ impl Display for Type {
                 write!(f, "\n}}")
             },
             Self::Nil => write!(f, "nil"),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::BigInt(value) => write!(f, "{}", value),
+            Self::Decimal(value) => write!(f, "{}d", value),
             Self::Float(value) => write!(f, "{}", value),
             Self::String(value) => write!(
                 f,
@@ -0,0 +1,116 @@
+use std::rc::Rc;
+
+use crate::{
+    environment::Slot,
+    error::LineInfo,
+    nodes::{pattern::Pattern, stmt::ImportType},
+    token::Token,
+    types::Type,
+};
+
+/// One instruction. Operands index into the tables of the `Chunk` it is in;
+/// jumps are to an index in its `code`.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Const(usize),
+    Pop,
+    Swap,
+
+    // variables, with the token of the name for errors
+    Get(Slot, usize),
+    Set(Slot, usize),
+    /// a variable the resolver found no declaration for
+    Undefined(usize),
+    Define(usize),
+    /// destructure a declaration's value, defining the pattern's bindings
+    Bind(usize),
+    /// destructure an assignment's value into the slots of `Chunk::unpacks`
+    Unpack(usize),
+    PushScope,
+    PopScope,
+
+    Add,
+    Sub,
+    Mult,
+    Div,
+    Mod,
+    Pow,
+    Eq,
+    NotEq,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Neg,
+    Not,
+
+    Jump(usize),
+    /// pops the condition
+    JumpIfFalse(usize),
+    // for `||` and `&&`: jump keeping the value, or pop it and carry on
+    JumpIfTrueOrPop(usize),
+    JumpIfFalseOrPop(usize),
+
+    /// the number of arguments, and the `Chunk::keywords` naming the optional
+    /// arguments after them
+    Call(usize, usize),
+    /// a call replacing the current one, for `return f(x)`
+    TailCall(usize, usize),
+    /// make a function of a `Chunk::protos`, taking its optional arguments'
+    /// defaults off the stack
+    Closure(usize),
+    Return,
+
+    Index,
+    SetIndex,
+    Prop(usize),
+    Array(usize),
+    Map(usize),
+    Template(usize),
+    Range(bool),
+
+    /// turn the iterable into the values for each iteration of a loop with this
+    /// many variables, and a counter
+    Iter(usize),
+    /// push the next iteration's values, or jump when there are none
+    Next(usize, usize),
+    /// check the value against a pattern, defining its bindings, or jump if it doesn't match
+    Match(usize, usize),
+    NoMatch,
+
+    Throw,
+    /// errors jump to the `catch`, with the error's value
+    Try(usize),
+    /// errors jump to the `finally`, to be raised again by `Rethrow` after it
+    Finally(usize),
+    PopHandler,
+    Rethrow,
+
+    Use(usize),
+}
+
+/// Compiled code, with the tables its instructions refer to.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    /// where each instruction came from, for errors
+    pub lines: Vec<LineInfo>,
+    pub consts: Vec<Type>,
+    pub names: Vec<Token>,
+    pub patterns: Vec<Rc<Pattern>>,
+    // the slot of each binding, in `Pattern::bindings` order
+    pub unpacks: Vec<(Rc<Pattern>, Vec<Option<Slot>>)>,
+    pub keywords: Vec<Vec<String>>,
+    pub protos: Vec<Rc<Proto>>,
+    pub imports: Vec<(Token, ImportType)>,
+}
+
+/// A compiled function, before it is closed over an environment.
+#[derive(Debug)]
+pub struct Proto {
+    pub name: Token,
+    pub args: Vec<Token>,
+    /// the names of the optional arguments, in the order declared
+    pub optional: Vec<String>,
+    pub chunk: Rc<Chunk>,
+}
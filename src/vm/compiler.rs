@@ -0,0 +1,714 @@
+use std::{collections::HashMap, iter, rc::Rc};
+
+use crate::{
+    environment::Locals,
+    error::LineInfo,
+    nodes::{
+        expr::Expr,
+        pattern::Pattern,
+        stmt::{FnDecl, Stmt},
+    },
+    token::{TType, Token},
+    types::Type,
+};
+
+use super::chunk::{Chunk, Op, Proto};
+
+/// What the code being compiled is inside of, for `break`, `continue` and
+/// `return` to undo on their way out.
+enum Context<'a> {
+    Scope,
+    Loop {
+        /// the stack height the loop runs at
+        height: usize,
+        breaks: Vec<usize>,
+        continues: Vec<usize>,
+    },
+    /// a `try` with a `catch`
+    Handler,
+    /// a `try` with a `finally`, which jumping out of must run
+    Finally(&'a [Stmt]),
+}
+
+/// Compiles a resolved program to bytecode. It runs exactly the scopes the
+/// tree-walker would, so the slots the resolver chose still line up.
+pub struct Compiler<'a> {
+    locals: &'a Locals,
+    chunk: Chunk,
+    contexts: Vec<Context<'a>>,
+    /// how many values are on the stack, above the current function's
+    height: usize,
+    line: LineInfo,
+    /// whether this is a function's body, rather than a program's
+    function: bool,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(locals: &'a Locals) -> Self {
+        Self {
+            locals,
+            chunk: Chunk::default(),
+            contexts: Vec::new(),
+            height: 0,
+            line: LineInfo::new(0, 0),
+            function: false,
+        }
+    }
+
+    /// Compile a program, which returns the value of its last statement.
+    pub fn compile(mut self, nodes: &'a [Stmt]) -> Rc<Chunk> {
+        self.stmts(nodes, true);
+        self.emit(Op::Return);
+
+        Rc::new(self.chunk)
+    }
+
+    fn function(&self, decl: &'a FnDecl) -> Rc<Proto> {
+        let mut compiler = Compiler::new(self.locals);
+        compiler.function = true;
+        compiler.block(&decl.body, false);
+        compiler.constant(Type::Nil);
+        compiler.emit(Op::Return);
+
+        Rc::new(Proto {
+            name: decl.name.clone(),
+            args: decl.args.clone(),
+            optional: decl
+                .optional_args
+                .iter()
+                .map(|(tok, _)| match &tok.ttype {
+                    TType::Identifier(name) => name.clone(),
+                    _ => panic!(),
+                })
+                .collect(),
+            chunk: Rc::new(compiler.chunk),
+        })
+    }
+
+    // stmts
+    /// Compile statements, leaving the value of the last one if `keep`.
+    fn stmts(&mut self, stmts: &'a [Stmt], keep: bool) {
+        if keep && stmts.is_empty() {
+            self.constant(Type::Nil);
+        }
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.stmt(stmt, keep && i == stmts.len() - 1);
+        }
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt], keep: bool) {
+        self.push_scope();
+        self.stmts(stmts, keep);
+        self.pop_scope();
+    }
+
+    /// Compile a statement, leaving its value if `keep`.
+    fn stmt(&mut self, node: &'a Stmt, keep: bool) {
+        match node {
+            Stmt::ExprStmt(expr) => {
+                self.expr(expr);
+
+                if !keep {
+                    self.emit(Op::Pop);
+                }
+                return;
+            }
+            Stmt::IfStmt(cond, true_br, elif_brs, else_br) => {
+                self.if_(cond, true_br, elif_brs, else_br, keep);
+                return;
+            }
+            Stmt::Pub(stmt) => {
+                self.stmt(stmt, keep);
+                return;
+            }
+            Stmt::VarDecl(decls) => {
+                for (pattern, val) in decls {
+                    self.expr(val);
+
+                    match pattern {
+                        Pattern::Binding(tok) => {
+                            let name = self.name(tok);
+                            self.emit(Op::Define(name));
+                        }
+                        _ => {
+                            self.chunk.patterns.push(Rc::new(pattern.clone()));
+                            self.emit(Op::Bind(self.chunk.patterns.len() - 1));
+                        }
+                    }
+                }
+            }
+            Stmt::Block(stmts) => self.block(stmts, false),
+            Stmt::WhileStmt(cond, block) => {
+                let start = self.chunk.code.len();
+                self.push_loop();
+                self.expr(cond);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.block(block, false);
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.pop_loop(start);
+            }
+            Stmt::DoWhile(block, cond) => {
+                let start = self.chunk.code.len();
+                self.push_loop();
+                self.block(block, false);
+                let check = self.chunk.code.len();
+                self.expr(cond);
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.pop_loop(check);
+            }
+            Stmt::ForIn(names, tok, iterable, block) => {
+                self.expr(iterable);
+                self.emit_at(Op::Iter(names.len()), tok);
+
+                let start = self.chunk.code.len();
+                self.push_loop();
+                let exit = self.emit(Op::Next(names.len(), 0));
+
+                self.push_scope();
+                for name in names {
+                    let name = self.name(name);
+                    self.emit(Op::Define(name));
+                }
+                self.block(block, false);
+                self.pop_scope();
+
+                self.emit(Op::Jump(start));
+                self.patch(exit);
+                self.pop_loop(start);
+
+                // the iteration values and counter
+                self.emit(Op::Pop);
+                self.emit(Op::Pop);
+            }
+            Stmt::Break(_) => {
+                let jump = self.jump_out();
+
+                if let Some(Context::Loop { breaks, .. }) = self.innermost_loop() {
+                    breaks.push(jump);
+                }
+            }
+            Stmt::Continue(_) => {
+                let jump = self.jump_out();
+
+                if let Some(Context::Loop { continues, .. }) = self.innermost_loop() {
+                    continues.push(jump);
+                }
+            }
+            Stmt::Return(_, Some(Expr::Call(func, tok, args, optional_args)))
+                if self.tail_calls() =>
+            {
+                let height = self.height;
+                self.call(func, tok, args, optional_args, true);
+                self.emit(Op::Return);
+                self.height = height;
+            }
+            Stmt::Return(_, val) => {
+                let height = self.height;
+
+                match val {
+                    Some(val) => self.expr(val),
+                    None => self.constant(Type::Nil),
+                }
+
+                // run the `finally` blocks being left, if there are any
+                if let Some(outermost) = self
+                    .contexts
+                    .iter()
+                    .position(|context| matches!(context, Context::Finally(_)))
+                {
+                    self.leave(outermost);
+                }
+
+                self.emit(Op::Return);
+                self.height = height;
+            }
+            Stmt::Throw(t, val) => {
+                self.expr(val);
+                self.emit_at(Op::Throw, t);
+            }
+            Stmt::Try(body, catch, finally) => {
+                let finally_at = finally.as_ref().map(|finally| {
+                    let at = self.emit(Op::Finally(0));
+                    self.contexts.push(Context::Finally(finally.as_slice()));
+                    at
+                });
+
+                match catch {
+                    Some((name, handler)) => {
+                        let catch_at = self.emit(Op::Try(0));
+                        self.contexts.push(Context::Handler);
+                        self.block(body, false);
+                        self.emit(Op::PopHandler);
+                        self.contexts.pop();
+                        let skip = self.emit(Op::Jump(0));
+
+                        // the error's value is pushed when jumping here
+                        self.patch(catch_at);
+                        self.height += 1;
+                        self.push_scope();
+                        let name = self.name(name);
+                        self.emit(Op::Define(name));
+                        self.block(handler, false);
+                        self.pop_scope();
+                        self.patch(skip);
+                    }
+                    None => self.block(body, false),
+                }
+
+                if let (Some(at), Some(finally)) = (finally_at, finally) {
+                    self.emit(Op::PopHandler);
+                    self.contexts.pop();
+                    self.block(finally, false);
+                    let skip = self.emit(Op::Jump(0));
+
+                    self.patch(at);
+                    self.block(finally, false);
+                    self.emit(Op::Rethrow);
+                    self.patch(skip);
+                }
+            }
+            Stmt::Function(decl) => {
+                self.closure(decl);
+                let name = self.name(&decl.name);
+                self.emit(Op::Define(name));
+            }
+            Stmt::UseStmt(module, import_type) => {
+                self.chunk
+                    .imports
+                    .push((module.clone(), import_type.clone()));
+                self.emit(Op::Use(self.chunk.imports.len() - 1));
+            }
+        }
+
+        if keep {
+            self.constant(Type::Nil);
+        }
+    }
+
+    fn if_(
+        &mut self,
+        cond: &'a Expr,
+        true_br: &'a [Stmt],
+        elif_brs: &'a [(Expr, Vec<Stmt>)],
+        else_br: &'a Option<Vec<Stmt>>,
+        keep: bool,
+    ) {
+        let mut ends = Vec::new();
+        let height = self.height;
+
+        for (cond, block) in iter::once((cond, true_br)).chain(
+            elif_brs
+                .iter()
+                .map(|(cond, block)| (cond, block.as_slice())),
+        ) {
+            self.expr(cond);
+            let next = self.emit(Op::JumpIfFalse(0));
+            self.block(block, keep);
+            ends.push(self.emit(Op::Jump(0)));
+            self.patch(next);
+            self.height = height;
+        }
+
+        match else_br {
+            Some(block) => self.block(block, keep),
+            None if keep => self.constant(Type::Nil),
+            None => {}
+        }
+
+        for end in ends {
+            self.patch(end);
+        }
+    }
+
+    fn call(
+        &mut self,
+        func: &'a Expr,
+        tok: &Token,
+        args: &'a [Expr],
+        optional_args: &'a HashMap<String, Expr>,
+        tail: bool,
+    ) {
+        self.expr(func);
+
+        for arg in args {
+            self.expr(arg);
+        }
+
+        let mut keywords = Vec::new();
+        for (name, expr) in optional_args {
+            self.expr(expr);
+            keywords.push(name.clone());
+        }
+
+        self.chunk.keywords.push(keywords);
+        let keywords = self.chunk.keywords.len() - 1;
+        if tail {
+            self.emit_at(Op::TailCall(args.len(), keywords), tok);
+        } else {
+            self.emit_at(Op::Call(args.len(), keywords), tok);
+        }
+    }
+
+    fn closure(&mut self, decl: &'a FnDecl) {
+        for (_, expr) in &decl.optional_args {
+            self.expr(expr);
+        }
+
+        let proto = self.function(decl);
+        self.chunk.protos.push(proto);
+        self.emit(Op::Closure(self.chunk.protos.len() - 1));
+    }
+
+    // exprs
+    fn expr(&mut self, node: &'a Expr) {
+        match node {
+            Expr::Binary(left, tok, right) => {
+                self.expr(left);
+                self.expr(right);
+
+                let op = match tok.ttype {
+                    TType::Plus => Op::Add,
+                    TType::Minus => Op::Sub,
+                    TType::Times => Op::Mult,
+                    TType::Divide => Op::Div,
+                    TType::Mod => Op::Mod,
+                    TType::Pow => Op::Pow,
+
+                    TType::EqEq => Op::Eq,
+                    TType::NotEq => Op::NotEq,
+
+                    TType::Less => Op::Less,
+                    TType::Greater => Op::Greater,
+                    TType::LessEq => Op::LessEq,
+                    TType::GreaterEq => Op::GreaterEq,
+                    _ => panic!(),
+                };
+                self.emit_at(op, tok);
+            }
+            Expr::Grouping(expr) => self.expr(expr),
+            Expr::Literal(val) => self.constant(val.clone()),
+            Expr::Unary(tok, right) => {
+                self.expr(right);
+
+                let op = match tok.ttype {
+                    TType::Not => Op::Not,
+                    TType::Minus => Op::Neg,
+                    _ => panic!(),
+                };
+                self.emit_at(op, tok);
+            }
+            Expr::Variable(id, v) => {
+                let name = self.name(v);
+
+                match self.locals.get(*id) {
+                    Some(slot) => self.emit_at(Op::Get(slot, name), v),
+                    None => self.emit_at(Op::Undefined(name), v),
+                };
+            }
+            Expr::Assign(id, k, v) => {
+                self.expr(v);
+                let name = self.name(k);
+
+                match self.locals.get(*id) {
+                    Some(slot) => self.emit_at(Op::Set(slot, name), k),
+                    None => {
+                        self.emit(Op::Pop);
+                        self.emit_at(Op::Undefined(name), k)
+                    }
+                };
+            }
+            Expr::Destructure(pattern, ids, v) => {
+                self.expr(v);
+
+                let slots = ids.iter().map(|id| self.locals.get(*id)).collect();
+                self.chunk.unpacks.push((pattern.clone(), slots));
+                self.emit(Op::Unpack(self.chunk.unpacks.len() - 1));
+            }
+            Expr::Block(stmts) => self.block(stmts, true),
+            Expr::Logical(left, tok, right) => {
+                self.expr(left);
+
+                let jump = if tok.ttype == TType::Or {
+                    self.emit(Op::JumpIfTrueOrPop(0))
+                } else {
+                    self.emit(Op::JumpIfFalseOrPop(0))
+                };
+
+                self.expr(right);
+                self.patch(jump);
+            }
+            Expr::Ternary(condition, true_br, else_br) => {
+                self.expr(condition);
+                let else_at = self.emit(Op::JumpIfFalse(0));
+                self.expr(true_br);
+                let end = self.emit(Op::Jump(0));
+
+                self.patch(else_at);
+                self.height -= 1;
+                self.expr(else_br);
+                self.patch(end);
+            }
+            Expr::Call(func, tok, args, optional_args) => {
+                self.call(func, tok, args, optional_args, false)
+            }
+            Expr::IfExpr(cond, true_br, elif_brs, else_br) => {
+                self.if_(cond, true_br, elif_brs, else_br, true)
+            }
+            Expr::Get(val, tok, key) => {
+                self.expr(val);
+                self.expr(key);
+                self.emit_at(Op::Index, tok);
+            }
+            Expr::Set(var, brack, i, val) => {
+                self.expr(var);
+                self.expr(i);
+                self.expr(val);
+                self.emit_at(Op::SetIndex, brack);
+            }
+            Expr::Prop(var, prop) => {
+                self.expr(var);
+                let name = self.name(prop);
+                self.emit_at(Op::Prop(name), prop);
+            }
+            Expr::Template(parts) => {
+                for part in parts {
+                    self.expr(part);
+                }
+
+                self.emit(Op::Template(parts.len()));
+            }
+            Expr::Array(itms) => {
+                for itm in itms {
+                    self.expr(itm);
+                }
+
+                self.emit(Op::Array(itms.len()));
+            }
+            Expr::Map(items) => {
+                for (key, value) in items {
+                    self.expr(key);
+                    self.expr(value);
+                }
+
+                self.emit(Op::Map(items.len()));
+            }
+            Expr::Range(left, tok, right, inclusive) => {
+                self.expr(left);
+                self.expr(right);
+                self.emit_at(Op::Range(*inclusive), tok);
+            }
+            Expr::Lambda(decl) => self.closure(decl),
+            Expr::Match(tok, value, arms) => {
+                self.expr(value);
+
+                let mut ends = Vec::new();
+                for (pattern, guard, body) in arms {
+                    // bindings live in their own scope for the guard and body
+                    self.push_scope();
+                    self.chunk.patterns.push(Rc::new(pattern.clone()));
+                    let mut fails = vec![self.emit(Op::Match(self.chunk.patterns.len() - 1, 0))];
+
+                    if let Some(guard) = guard {
+                        self.expr(guard);
+                        fails.push(self.emit(Op::JumpIfFalse(0)));
+                    }
+
+                    self.expr(body);
+                    self.pop_scope();
+
+                    // the matched value is under the result
+                    self.emit(Op::Swap);
+                    self.emit(Op::Pop);
+                    ends.push(self.emit(Op::Jump(0)));
+
+                    for fail in fails {
+                        self.patch(fail);
+                    }
+                    self.emit(Op::PopScope);
+                }
+
+                self.emit_at(Op::NoMatch, tok);
+                self.height += 1;
+
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+        }
+    }
+
+    // control flow
+    fn push_loop(&mut self) {
+        self.contexts.push(Context::Loop {
+            height: self.height,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    /// End the innermost loop, sending its `break`s to the current position
+    /// and its `continue`s to `next`.
+    fn pop_loop(&mut self, next: usize) {
+        if let Some(Context::Loop {
+            breaks, continues, ..
+        }) = self.contexts.pop()
+        {
+            for jump in breaks {
+                self.patch(jump);
+            }
+
+            for jump in continues {
+                self.chunk.code[jump] = Op::Jump(next);
+            }
+        }
+    }
+
+    fn innermost_loop(&mut self) -> Option<&mut Context<'a>> {
+        self.contexts
+            .iter_mut()
+            .rev()
+            .find(|context| matches!(context, Context::Loop { .. }))
+    }
+
+    /// Leave everything inside the innermost loop, returning the jump to patch
+    /// to where the `break` or `continue` goes.
+    fn jump_out(&mut self) -> usize {
+        let height = self.height;
+        let index = match self
+            .contexts
+            .iter()
+            .rposition(|context| matches!(context, Context::Loop { .. }))
+        {
+            Some(index) => index,
+            // the resolver has rejected this already
+            None => panic!(),
+        };
+
+        if let Context::Loop { height: target, .. } = self.contexts[index] {
+            for _ in target..self.height {
+                self.emit(Op::Pop);
+            }
+        }
+
+        self.leave(index + 1);
+
+        let jump = self.emit(Op::Jump(0));
+        self.height = height;
+
+        jump
+    }
+
+    /// Undo the contexts from the innermost one out to `index`, running
+    /// the `finally` blocks among them.
+    fn leave(&mut self, index: usize) {
+        for i in (index..self.contexts.len()).rev() {
+            match self.contexts[i] {
+                Context::Scope => {
+                    self.emit(Op::PopScope);
+                }
+                Context::Handler => {
+                    self.emit(Op::PopHandler);
+                }
+                Context::Finally(finally) => {
+                    self.emit(Op::PopHandler);
+
+                    // the `finally` runs outside of its `try`
+                    let inside = self.contexts.split_off(i);
+                    self.block(finally, false);
+                    self.contexts.extend(inside);
+                }
+                Context::Loop { .. } => {}
+            }
+        }
+    }
+
+    // util
+    /// Whether a `return` of a call can replace the function making it, which it
+    /// can't if a `catch` or `finally` would have to run after the call.
+    fn tail_calls(&self) -> bool {
+        self.function
+            && !self
+                .contexts
+                .iter()
+                .any(|context| matches!(context, Context::Handler | Context::Finally(_)))
+    }
+
+    fn push_scope(&mut self) {
+        self.emit(Op::PushScope);
+        self.contexts.push(Context::Scope);
+    }
+
+    fn pop_scope(&mut self) {
+        self.emit(Op::PopScope);
+        self.contexts.pop();
+    }
+
+    fn constant(&mut self, val: Type) {
+        self.chunk.consts.push(val);
+        self.emit(Op::Const(self.chunk.consts.len() - 1));
+    }
+
+    fn name(&mut self, tok: &Token) -> usize {
+        self.chunk.names.push(tok.clone());
+        self.chunk.names.len() - 1
+    }
+
+    fn emit_at(&mut self, op: Op, tok: &Token) -> usize {
+        self.line = tok.lineinfo;
+        self.emit(op)
+    }
+
+    /// Add an instruction, keeping track of how it changes the stack.
+    fn emit(&mut self, op: Op) -> usize {
+        let (pops, pushes) = match op {
+            Op::Const(_) | Op::Get(..) | Op::Undefined(_) | Op::Closure(_) => (0, 1),
+            Op::Pop | Op::Define(_) | Op::Bind(_) | Op::JumpIfFalse(_) | Op::Return => (1, 0),
+            Op::JumpIfTrueOrPop(_) | Op::JumpIfFalseOrPop(_) | Op::Throw | Op::NoMatch => (1, 0),
+            Op::Add | Op::Sub | Op::Mult | Op::Div | Op::Mod | Op::Pow => (2, 1),
+            Op::Eq | Op::NotEq | Op::Less | Op::Greater | Op::LessEq | Op::GreaterEq => (2, 1),
+            Op::Index | Op::Range(_) => (2, 1),
+            Op::SetIndex => (3, 1),
+            Op::Array(n) | Op::Template(n) => (n, 1),
+            Op::Map(n) => (n * 2, 1),
+            Op::Call(n, keywords) | Op::TailCall(n, keywords) => {
+                (1 + n + self.chunk.keywords[keywords].len(), 1)
+            }
+            Op::Iter(_) => (1, 2),
+            Op::Next(n, _) => (0, n),
+            _ => (0, 0),
+        };
+
+        // a closure takes its optional arguments' defaults
+        let pops = match op {
+            Op::Closure(proto) => self.chunk.protos[proto].optional.len(),
+            _ => pops,
+        };
+
+        self.height = self.height + pushes - pops;
+        self.chunk.code.push(op);
+        self.chunk.lines.push(self.line);
+
+        self.chunk.code.len() - 1
+    }
+
+    /// Point the jump at `at` to the current position.
+    fn patch(&mut self, at: usize) {
+        let here = self.chunk.code.len();
+
+        self.chunk.code[at] = match self.chunk.code[at] {
+            Op::Jump(_) => Op::Jump(here),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(here),
+            Op::JumpIfTrueOrPop(_) => Op::JumpIfTrueOrPop(here),
+            Op::JumpIfFalseOrPop(_) => Op::JumpIfFalseOrPop(here),
+            Op::Next(n, _) => Op::Next(n, here),
+            Op::Match(pattern, _) => Op::Match(pattern, here),
+            Op::Try(_) => Op::Try(here),
+            Op::Finally(_) => Op::Finally(here),
+            op => op,
+        };
+    }
+}
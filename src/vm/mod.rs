@@ -0,0 +1,631 @@
+/*
+    An alternative to the tree-walking interpreter: the program is compiled to
+    bytecode (see `compiler`) and run on a stack machine. Variables still live
+    in an `Environment`, in the slots the resolver gave them, and values,
+    modules and errors are shared with the interpreter, so the two can call
+    each other's functions.
+*/
+
+use std::{cell::RefCell, collections::HashMap, mem, rc::Rc};
+
+use crate::{
+    environment::Environment,
+    error::{Error, ErrorType, Frame, LineInfo, Source},
+    functions::{Call, CompiledFn, FuncType},
+    interpreter::Interpreter,
+    loader::Names,
+    token::TType,
+    types::{array::Array, map::Map, ops::TResult, Type},
+};
+
+use chunk::{Chunk, Op};
+
+pub mod chunk;
+pub mod compiler;
+
+/// A call in progress on the VM.
+struct CallFrame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    /// the height of the stack before the call
+    base: usize,
+    // the caller's environment and source file, for when the call returns
+    environ: Environment,
+    source: Option<Rc<Source>>,
+}
+
+/// Where to go when an error is raised inside a `try`.
+struct Handler {
+    /// the frame the `try` is in, and its stack height
+    frame: usize,
+    height: usize,
+    target: usize,
+    environ: Environment,
+    /// the length of `Interpreter::frames` at the `try`
+    trace: usize,
+    /// whether it is for a `finally`, which gets the error itself rather than its value
+    finally: bool,
+    /// the length of `Vm::pending` at the `try`
+    pending: usize,
+}
+
+pub struct Vm<'a> {
+    interpreter: &'a mut Interpreter,
+    stack: Vec<Type>,
+    frames: Vec<CallFrame>,
+    handlers: Vec<Handler>,
+    /// errors put aside while a `finally` runs
+    pending: Vec<Error>,
+    /// the length of `Interpreter::frames` when the VM started
+    trace: usize,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        let trace = interpreter.frames.len();
+
+        Self {
+            interpreter,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            handlers: Vec::new(),
+            pending: Vec::new(),
+            trace,
+        }
+    }
+
+    /// Run a compiled program in the interpreter's environment.
+    pub fn run(&mut self, chunk: Rc<Chunk>) -> Result<Type, Error> {
+        self.frames.push(CallFrame {
+            chunk,
+            ip: 0,
+            base: 0,
+            environ: self.interpreter.environ.clone(),
+            source: self.interpreter.source.clone(),
+        });
+
+        self.execute()
+    }
+
+    /// Call a compiled function from outside the VM.
+    pub fn call(
+        &mut self,
+        func: &CompiledFn,
+        args: Vec<Type>,
+        opt_args: HashMap<String, Type>,
+    ) -> Result<Type, Error> {
+        let keywords: Vec<String> = opt_args.keys().cloned().collect();
+        self.stack.extend(args);
+        self.stack
+            .extend(keywords.iter().map(|name| opt_args[name].clone()));
+
+        self.enter(func, 0, &keywords);
+        self.execute()
+    }
+
+    /// Start running `func`, with its arguments on the stack from `base`.
+    fn enter(&mut self, func: &CompiledFn, base: usize, keywords: &[String]) {
+        let mut environ = func.closure.clone();
+        environ.push_scope();
+
+        let args = &self.stack[base..];
+        for (i, name) in func.proto.args.iter().enumerate() {
+            match &name.ttype {
+                TType::Identifier(n) => environ.define(n, &args[i]),
+                _ => panic!(),
+            }
+        }
+
+        let given = &args[func.proto.args.len()..];
+        for (name, default) in &func.optional_args {
+            let val = match keywords.iter().position(|keyword| keyword == name) {
+                Some(i) => &given[i],
+                None => default,
+            };
+
+            environ.define(name, val);
+        }
+
+        self.stack.truncate(base);
+        self.frames.push(CallFrame {
+            chunk: func.proto.chunk.clone(),
+            ip: 0,
+            base,
+            environ: mem::replace(&mut self.interpreter.environ, environ),
+            source: mem::replace(&mut self.interpreter.source, func.source.clone()),
+        });
+    }
+
+    /// Run until the outermost frame returns, catching errors along the way.
+    fn execute(&mut self) -> Result<Type, Error> {
+        loop {
+            match self.dispatch() {
+                Ok(val) => return Ok(val),
+                Err(e) => self.catch(e)?,
+            }
+        }
+    }
+
+    fn dispatch(&mut self) -> Result<Type, Error> {
+        let frame = self.frames.last().unwrap();
+        let mut chunk = frame.chunk.clone();
+        let mut ip = frame.ip;
+
+        loop {
+            let op = chunk.code[ip];
+            let line = chunk.lines[ip];
+            ip += 1;
+
+            match op {
+                Op::Const(i) => self.stack.push(chunk.consts[i].clone()),
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Swap => {
+                    let len = self.stack.len();
+                    self.stack.swap(len - 1, len - 2);
+                }
+
+                Op::Get(slot, name) => {
+                    let val = self.interpreter.environ.get(slot, &chunk.names[name])?;
+                    self.stack.push(val);
+                }
+                Op::Set(slot, name) => {
+                    let val = self.stack.last().unwrap();
+                    self.interpreter
+                        .environ
+                        .assign(slot, &chunk.names[name], val)?;
+                }
+                Op::Undefined(name) => return Err(Environment::undefined(&chunk.names[name])),
+                Op::Define(name) => {
+                    let val = self.pop();
+                    self.interpreter.define(&chunk.names[name], &val);
+                }
+                Op::Bind(pattern) => {
+                    let val = self.pop();
+
+                    for (tok, val) in self
+                        .interpreter
+                        .destructure(&chunk.patterns[pattern], &val)?
+                    {
+                        self.interpreter.define(&tok, &val);
+                    }
+                }
+                Op::Unpack(i) => {
+                    let (pattern, slots) = &chunk.unpacks[i];
+                    let val = self.peek().clone();
+
+                    // matching binds in the same order as `Pattern::bindings`
+                    for (slot, (tok, val)) in slots
+                        .iter()
+                        .zip(self.interpreter.destructure(pattern, &val)?)
+                    {
+                        match slot {
+                            Some(slot) => self.interpreter.environ.assign(*slot, &tok, &val)?,
+                            None => return Err(Environment::undefined(&tok)),
+                        }
+                    }
+                }
+                Op::PushScope => self.interpreter.environ.push_scope(),
+                Op::PopScope => self.interpreter.environ.pop_scope(),
+
+                Op::Add => self.binary(line, Type::add)?,
+                Op::Sub => self.binary(line, Type::sub)?,
+                Op::Mult => self.binary(line, Type::mult)?,
+                Op::Div => self.binary(line, Type::div)?,
+                Op::Mod => self.binary(line, Type::modulo)?,
+                Op::Pow => self.binary(line, Type::pow)?,
+                Op::Eq => self.compare(|l, r| l == r),
+                Op::NotEq => self.compare(|l, r| l != r),
+                Op::Less => self.compare(|l, r| l < r),
+                Op::Greater => self.compare(|l, r| l > r),
+                Op::LessEq => self.compare(|l, r| l <= r),
+                Op::GreaterEq => self.compare(|l, r| l >= r),
+                Op::Neg => {
+                    let val = self.pop().neg();
+                    self.push(val, line)?;
+                }
+                Op::Not => {
+                    let val = self.pop();
+                    self.stack
+                        .push(Type::Bool(self.interpreter.is_truthy(&val)));
+                }
+
+                Op::Jump(to) => ip = to,
+                Op::JumpIfFalse(to) => {
+                    let cond = self.pop();
+
+                    if !self.interpreter.is_truthy(&cond) {
+                        ip = to;
+                    }
+                }
+                Op::JumpIfTrueOrPop(to) => {
+                    if self.interpreter.is_truthy(self.peek()) {
+                        ip = to;
+                    } else {
+                        self.stack.pop();
+                    }
+                }
+                Op::JumpIfFalseOrPop(to) => {
+                    if !self.interpreter.is_truthy(self.peek()) {
+                        ip = to;
+                    } else {
+                        self.stack.pop();
+                    }
+                }
+
+                Op::Call(argc, keywords) | Op::TailCall(argc, keywords) => {
+                    let keywords = &chunk.keywords[keywords];
+                    let base = self.stack.len() - argc - keywords.len();
+
+                    let func = match &self.stack[base - 1] {
+                        Type::Func(func) => func.clone(),
+                        _ => {
+                            return Err(Error::new(
+                                line,
+                                "Only functions can be called.".into(),
+                                ErrorType::TypeError,
+                            ))
+                        }
+                    };
+
+                    let ar = func.arity();
+                    if argc != ar {
+                        return Err(Error::new(
+                            line,
+                            format!(
+                                "Expected {} argument{}, but got {}.",
+                                ar,
+                                if ar == 1 { "" } else { "s" },
+                                argc
+                            ),
+                            ErrorType::TypeError,
+                        ));
+                    }
+
+                    let frame = Frame {
+                        name: func.name(),
+                        call: line,
+                        source: self.interpreter.source.clone(),
+                    };
+
+                    // a compiled function's tail call replaces the frame making it,
+                    // which the `Return` after it is then never reached to leave
+                    if let (Op::TailCall(..), FuncType::Compiled(func)) = (op, &func) {
+                        let call = self.frames.pop().unwrap();
+                        let args = self.stack.split_off(base);
+                        self.stack.truncate(call.base);
+                        self.stack.extend(args);
+
+                        self.interpreter.environ = call.environ;
+                        self.interpreter.source = call.source;
+                        if let Some(top) = self.interpreter.frames.last_mut() {
+                            *top = frame;
+                        }
+
+                        self.enter(func, call.base, keywords);
+                        chunk = func.proto.chunk.clone();
+                        ip = 0;
+                        continue;
+                    }
+
+                    if let Some(error) = self.interpreter.depth_error(line) {
+                        return Err(error);
+                    }
+                    self.interpreter.frames.push(frame);
+
+                    match func {
+                        FuncType::Compiled(func) => {
+                            self.frames.last_mut().unwrap().ip = ip;
+                            self.stack.remove(base - 1);
+                            self.enter(&func, base - 1, keywords);
+
+                            chunk = func.proto.chunk.clone();
+                            ip = 0;
+                            continue;
+                        }
+                        func => {
+                            let opt_args = keywords
+                                .iter()
+                                .cloned()
+                                .zip(self.stack.drain(base + argc..))
+                                .collect();
+                            let args = self.stack.split_off(base);
+                            self.stack.pop();
+
+                            let out = match func {
+                                FuncType::Native(_) => func
+                                    .call(self.interpreter, args, opt_args)
+                                    .map_err(|e| e.at(line)),
+                                _ => func.call(self.interpreter, args, opt_args),
+                            }?;
+
+                            self.interpreter.frames.pop();
+                            self.stack.push(out);
+                        }
+                    }
+                }
+                Op::Closure(proto) => {
+                    let proto = chunk.protos[proto].clone();
+                    let defaults = self
+                        .stack
+                        .split_off(self.stack.len() - proto.optional.len());
+
+                    let func = CompiledFn::new(
+                        proto.clone(),
+                        proto.optional.iter().cloned().zip(defaults).collect(),
+                        self.interpreter.environ.clone(),
+                        self.interpreter.source.clone(),
+                    );
+                    self.stack.push(Type::Func(FuncType::Compiled(func)));
+                }
+                Op::Return => {
+                    let val = self.pop();
+                    let frame = self.frames.pop().unwrap();
+
+                    self.stack.truncate(frame.base);
+                    self.interpreter.environ = frame.environ;
+                    self.interpreter.source = frame.source;
+
+                    let depth = self.frames.len();
+                    while self.handlers.last().is_some_and(|h| h.frame >= depth) {
+                        self.handlers.pop();
+                    }
+
+                    match self.frames.last() {
+                        Some(frame) => {
+                            self.interpreter.frames.pop();
+                            self.stack.push(val);
+
+                            chunk = frame.chunk.clone();
+                            ip = frame.ip;
+                        }
+                        None => return Ok(val),
+                    }
+                }
+
+                Op::Index => {
+                    let key = self.pop();
+                    let val = self.pop().index(key);
+                    self.push(val, line)?;
+                }
+                Op::SetIndex => {
+                    let val = self.pop();
+                    let i = self.pop();
+                    let out = self.pop().assign(i, val);
+                    self.push(out, line)?;
+                }
+                Op::Prop(name) => {
+                    let prop = &chunk.names[name];
+
+                    let out = match self.pop() {
+                        Type::Module(module) => {
+                            let prop_string = match &prop.ttype {
+                                TType::Identifier(v) => v,
+                                _ => panic!(),
+                            };
+
+                            match module.get(prop_string) {
+                                Some(out) => out,
+                                None => return Err(Names::of(&module).missing(prop, &module.name)),
+                            }
+                        }
+                        _ => {
+                            return Err(Error::new(
+                                line,
+                                "Only modules have properties.".into(),
+                                ErrorType::TypeError,
+                            ))
+                        }
+                    };
+                    self.stack.push(out);
+                }
+                Op::Array(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n);
+                    self.stack
+                        .push(Type::Array(Rc::new(RefCell::new(Array::new(items)))));
+                }
+                Op::Map(n) => {
+                    let mut out = HashMap::new();
+                    let mut items = self.stack.drain(self.stack.len() - n * 2..);
+
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        out.insert(key.to_string(), value);
+                    }
+
+                    drop(items);
+                    self.stack
+                        .push(Type::Map(Rc::new(RefCell::new(Map::new(out)))));
+                }
+                Op::Template(n) => {
+                    let mut out = String::new();
+
+                    for part in self.stack.drain(self.stack.len() - n..) {
+                        out += &part.to_string();
+                    }
+
+                    self.stack.push(Type::String(out));
+                }
+                Op::Range(inclusive) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let out = range(left, right, inclusive, line)?;
+                    self.stack.push(out);
+                }
+
+                Op::Iter(names) => {
+                    let iterable = self.pop();
+                    let items = iterable
+                        .iter()
+                        .map_err(|(msg, etype)| Error::new(line, msg, etype))?;
+
+                    // a single variable walks the keys of a map, but the values of anything else
+                    let mut values = Vec::new();
+                    for (key, value) in items {
+                        match (names, &iterable) {
+                            (1, Type::Map(_)) => values.push(key),
+                            (1, _) => values.push(value),
+                            _ => values.extend([value, key]),
+                        }
+                    }
+
+                    self.stack
+                        .push(Type::Array(Rc::new(RefCell::new(Array::new(values)))));
+                    self.stack.push(Type::Int(0));
+                }
+                Op::Next(names, exit) => {
+                    let len = self.stack.len();
+                    let i = match self.stack[len - 1] {
+                        Type::Int(i) => i as usize,
+                        _ => unreachable!(),
+                    };
+                    let values = match &self.stack[len - 2] {
+                        Type::Array(values) => values.clone(),
+                        _ => unreachable!(),
+                    };
+                    let values = &values.borrow().arr;
+
+                    // pushed last first, so they are defined in order
+                    match values.get(i * names..(i + 1) * names) {
+                        Some(next) => {
+                            self.stack[len - 1] = Type::Int(i as i64 + 1);
+                            self.stack.extend(next.iter().cloned());
+                        }
+                        None => ip = exit,
+                    }
+                }
+                Op::Match(pattern, fail) => {
+                    let mut bindings = Vec::new();
+
+                    if chunk.patterns[pattern].matches(self.peek(), &mut bindings) {
+                        for (tok, val) in bindings {
+                            self.interpreter.define(&tok, &val);
+                        }
+                    } else {
+                        ip = fail;
+                    }
+                }
+                Op::NoMatch => {
+                    let value = self.pop();
+
+                    return Err(Error::new(
+                        line,
+                        format!("No pattern matched the value {}.", value),
+                        ErrorType::MatchError,
+                    ));
+                }
+
+                Op::Throw => {
+                    let val = self.pop();
+                    return Err(Error::thrown(line, val));
+                }
+                Op::Try(target) | Op::Finally(target) => self.handlers.push(Handler {
+                    frame: self.frames.len() - 1,
+                    height: self.stack.len(),
+                    target,
+                    environ: self.interpreter.environ.clone(),
+                    trace: self.interpreter.frames.len(),
+                    finally: matches!(op, Op::Finally(_)),
+                    pending: self.pending.len(),
+                }),
+                Op::PopHandler => {
+                    self.handlers.pop();
+                }
+                Op::Rethrow => return Err(self.pending.pop().unwrap()),
+
+                Op::Use(i) => {
+                    let (module, import_type) = &chunk.imports[i];
+                    self.interpreter.import(module, import_type)?;
+                }
+            }
+        }
+    }
+
+    /// Send an error to the innermost `try`, or out of the VM if there is none.
+    fn catch(&mut self, e: Error) -> Result<(), Error> {
+        let mut e = e.with_trace(&self.interpreter.frames);
+        let frame = self.handlers.last().map_or(0, |handler| handler.frame + 1);
+
+        // leave the calls the error was raised in
+        while self.frames.len() > frame {
+            let call = self.frames.pop().unwrap();
+            e = e.in_source(&self.interpreter.source);
+
+            self.interpreter.environ = call.environ;
+            self.interpreter.source = call.source;
+        }
+
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => {
+                self.interpreter.frames.truncate(self.trace);
+                return Err(e);
+            }
+        };
+
+        self.stack.truncate(handler.height);
+        self.interpreter.environ = handler.environ;
+        self.interpreter.frames.truncate(handler.trace);
+        self.frames.last_mut().unwrap().ip = handler.target;
+        self.pending.truncate(handler.pending);
+
+        if handler.finally {
+            self.pending.push(e);
+        } else {
+            self.stack.push(e.to_value());
+        }
+
+        Ok(())
+    }
+
+    // util
+    fn pop(&mut self) -> Type {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self) -> &Type {
+        self.stack.last().unwrap()
+    }
+
+    fn push(&mut self, val: TResult, line: LineInfo) -> Result<(), Error> {
+        match val {
+            Ok(val) => {
+                self.stack.push(val);
+                Ok(())
+            }
+            Err((msg, etype)) => Err(Error::new(line, msg, etype)),
+        }
+    }
+
+    fn binary(&mut self, line: LineInfo, op: fn(&Type, &Type) -> TResult) -> Result<(), Error> {
+        let right = self.pop();
+        let left = self.pop();
+        self.push(op(&left, &right), line)
+    }
+
+    fn compare(&mut self, op: fn(&Type, &Type) -> bool) {
+        let right = self.pop();
+        let left = self.pop();
+        self.stack.push(Type::Bool(op(&left, &right)));
+    }
+}
+
+fn range(left: Type, right: Type, inclusive: bool, line: LineInfo) -> Result<Type, Error> {
+    match (left, right) {
+        (Type::Int(l), Type::Int(r)) => {
+            let out: Vec<Type> = match (l > r, inclusive) {
+                (true, true) => (r..=l).rev().map(Type::Int).collect(),
+                (true, false) => (r + 1..=l).rev().map(Type::Int).collect(),
+                (false, true) => (l..=r).map(Type::Int).collect(),
+                (false, false) => (l..r).map(Type::Int).collect(),
+            };
+
+            Ok(Type::Array(Rc::new(RefCell::new(Array::new(out)))))
+        }
+        _ => Err(Error::new(
+            line,
+            "Ranges can only contain integers.".into(),
+            ErrorType::TypeError,
+        )),
+    }
+}